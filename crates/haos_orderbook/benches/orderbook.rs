@@ -0,0 +1,83 @@
+//! Order book throughput at different resting book sizes.
+//!
+//! Run with `cargo bench -p haos_orderbook`; results are reported per
+//! operation, so throughput is `1s / ns_per_iter`.
+#![feature(test)]
+
+extern crate test;
+
+use haos_orderbook::orderbook::{
+    order::{Order, OrderSide},
+    OrderBook,
+};
+use test::{black_box, Bencher};
+
+const PRICE_LEVELS: u32 = 100;
+
+/// Builds an uncrossed book with `size` resting orders spread over
+/// `PRICE_LEVELS` prices per side: buys below 1000, sells from 1000 up.
+fn resting_book(size: u32) -> OrderBook {
+    let mut book = OrderBook::new();
+    for id in 1..=size {
+        let level = id % PRICE_LEVELS;
+        let order = if id % 2 == 0 {
+            Order::new(id, 0, 100, 999 - level, OrderSide::Buy)
+        } else {
+            Order::new(id, 0, 100, 1000 + level, OrderSide::Sell)
+        };
+        book.add_order(order);
+    }
+    book
+}
+
+fn add_order(b: &mut Bencher, size: u32) {
+    let mut book = resting_book(size);
+    let mut id = size;
+    b.iter(|| {
+        // add and cancel so the book stays at the same depth
+        id += 1;
+        let order = Order::new(id, 0, 100, 950 + id % PRICE_LEVELS, OrderSide::Buy);
+        book.add_order(black_box(order));
+        book.remove_order(black_box(id.into()));
+    });
+}
+
+fn update_order(b: &mut Bencher, size: u32) {
+    let mut book = resting_book(size);
+    let mut id = 0;
+    b.iter(|| {
+        // partially fill a resting buy order in place
+        id = (id + 2) % size;
+        let order = book.get_order(id.max(2).into()).unwrap().clone();
+        let volume = if order.volume.get() > 1 {
+            order.volume - 1.into()
+        } else {
+            100.into()
+        };
+        book.update_order(black_box(Order { volume, ..order }));
+    });
+}
+
+fn find_matching_orders(b: &mut Bencher, size: u32) {
+    let mut book = resting_book(size);
+    // cross the top of the book once
+    book.add_order(Order::new(size + 1, 0, 100, 1000, OrderSide::Buy));
+    b.iter(|| black_box(book.find_matching_orders()));
+}
+
+macro_rules! bench_sizes {
+    ($op:ident: $($name:ident => $size:expr),+ $(,)?) => {
+        mod $op {
+            $(
+                #[bench]
+                fn $name(b: &mut test::Bencher) {
+                    super::$op(b, $size);
+                }
+            )+
+        }
+    };
+}
+
+bench_sizes!(add_order: orders_10k => 10_000, orders_100k => 100_000, orders_1m => 1_000_000);
+bench_sizes!(update_order: orders_10k => 10_000, orders_100k => 100_000, orders_1m => 1_000_000);
+bench_sizes!(find_matching_orders: orders_10k => 10_000, orders_100k => 100_000, orders_1m => 1_000_000);
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use axum::{extract::Path, http::StatusCode, routing::put, Extension, Router};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use tower_http::add_extension::AddExtensionLayer;
+use tracing::info;
+
+use crate::orderbook::lifecycle::MarketState;
+
+/// A request of an operator, applied by the manager before it handles the
+/// next block.
+#[derive(Debug)]
+pub enum AdminCommand {
+    /// Moves a market to another lifecycle state.
+    SetMarketState {
+        contract_id: u32,
+        state: MarketState,
+        reply: oneshot::Sender<Result<()>>,
+    },
+}
+
+/// Sends admin commands to the manager.
+pub type AdminSender = mpsc::Sender<AdminCommand>;
+
+/// Routes of the admin endpoint. `PUT /markets/:contract_id/state` moves a
+/// market to the state in the body, e.g. `halted` or `resolved_yes`.
+pub fn router(commands: AdminSender) -> Router {
+    Router::new()
+        .route("/markets/:contract_id/state", put(set_market_state))
+        .layer(AddExtensionLayer::new(commands))
+}
+
+/// Serves the admin endpoint on `addr`.
+pub async fn serve(addr: &str, commands: AdminSender) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Admin endpoint listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(commands)).await?;
+    Ok(())
+}
+
+async fn set_market_state(
+    Extension(commands): Extension<AdminSender>,
+    Path(contract_id): Path<u32>,
+    body: String,
+) -> (StatusCode, String) {
+    let state = match body.trim().parse::<MarketState>() {
+        Ok(state) => state,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let (reply, replied) = oneshot::channel();
+    let command = AdminCommand::SetMarketState {
+        contract_id,
+        state,
+        reply,
+    };
+    if commands.send(command).await.is_err() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The manager is not running".to_string(),
+        );
+    }
+    match replied.await {
+        Ok(Ok(())) => (
+            StatusCode::OK,
+            format!("Market {} is {}", contract_id, state),
+        ),
+        // unknown markets and transitions the lifecycle does not allow
+        Ok(Err(e)) => (StatusCode::CONFLICT, e.to_string()),
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The manager stopped before applying the command".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Path, http::StatusCode, Extension};
+    use tokio::sync::mpsc;
+
+    use crate::{
+        admin::{set_market_state, AdminCommand},
+        orderbook::lifecycle::MarketState,
+    };
+
+    #[tokio::test]
+    async fn test_set_market_state() {
+        let (commands, mut received) = mpsc::channel(1);
+        // the manager's side, halting markets and refusing to resolve them
+        tokio::spawn(async move {
+            while let Some(AdminCommand::SetMarketState {
+                contract_id,
+                state,
+                reply,
+            }) = received.recv().await
+            {
+                let result = match state {
+                    MarketState::Halted if contract_id == 1 => Ok(()),
+                    _ => Err(anyhow::anyhow!("Cannot move market {}", contract_id)),
+                };
+                let _ = reply.send(result);
+            }
+        });
+
+        let request = |contract_id: u32, body: &str| {
+            set_market_state(
+                Extension(commands.clone()),
+                Path(contract_id),
+                body.to_string(),
+            )
+        };
+        assert_eq!(request(1, "halted\n").await.0, StatusCode::OK);
+        assert_eq!(request(1, "resolved_yes").await.0, StatusCode::CONFLICT);
+        assert_eq!(request(1, "paused").await.0, StatusCode::BAD_REQUEST);
+    }
+}
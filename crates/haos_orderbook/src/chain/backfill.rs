@@ -0,0 +1,127 @@
+use crate::constants::BACKFILL_SMALL_RESULT;
+
+/// Number of blocks logs are fetched for at once, shrinking when the
+/// provider rejects a range and growing back when results are small, but
+/// never to a size the provider rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackfillWindow {
+    size: u64,
+    max: u64,
+    // smallest size the provider rejected
+    ceiling: Option<u64>,
+}
+
+impl BackfillWindow {
+    pub fn new(max: u64) -> Self {
+        let max = max.max(1);
+        Self {
+            size: max,
+            max,
+            ceiling: None,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Last block of the chunk starting at `from`, at most `to`.
+    pub fn chunk_end(&self, from: u64, to: u64) -> u64 {
+        from.saturating_add(self.size - 1).min(to)
+    }
+
+    /// Halves the window after the provider rejected a range. Returns false
+    /// if it is a single block already.
+    pub fn shrink(&mut self) -> bool {
+        if self.size == 1 {
+            return false;
+        }
+        self.ceiling = Some(
+            self.ceiling
+                .map_or(self.size, |ceiling| ceiling.min(self.size)),
+        );
+        self.size /= 2;
+        true
+    }
+
+    /// Doubles the window, up to its maximum, after a chunk returned few logs.
+    /// A window that would reach a rejected size keeps its size.
+    pub fn record(&mut self, logs: usize) {
+        let grown = self.size.saturating_mul(2).min(self.max);
+        if logs < BACKFILL_SMALL_RESULT && self.ceiling.map_or(true, |ceiling| grown < ceiling) {
+            self.size = grown;
+        }
+    }
+}
+
+/// Whether a provider error means the range or its result was too large,
+/// providers only tell in the message. Throttling is not, it would shrink
+/// the window for good.
+pub fn is_range_too_large(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "-32005",
+        "block range",
+        "query returned more than",
+        "response size exceeded",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+        && !["429", "too many requests", "rate limit"]
+            .iter()
+            .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chain::backfill::{is_range_too_large, BackfillWindow};
+
+    #[test]
+    fn test_backfill_window() {
+        let mut window = BackfillWindow::new(1_000);
+        assert_eq!(window.chunk_end(1, 5_000), 1_000);
+        assert_eq!(window.chunk_end(4_501, 5_000), 5_000);
+
+        // a full chunk keeps the window, small ones grow it up to the maximum
+        window.record(0);
+        assert_eq!(window.size(), 1_000);
+        assert!(window.shrink());
+        assert!(window.shrink());
+        assert_eq!(window.size(), 250);
+        window.record(10_000);
+        assert_eq!(window.size(), 250);
+        // but never back to a size the provider rejected
+        window.record(0);
+        assert_eq!(window.size(), 250);
+
+        // a provider rejecting more than 300 blocks is asked for 250 at most
+        let mut window = BackfillWindow::new(2_000);
+        let mut rejected = 0;
+        for _ in 0..10 {
+            if window.size() > 300 {
+                assert!(window.shrink());
+                rejected += 1;
+            } else {
+                window.record(0);
+            }
+        }
+        assert_eq!(window.size(), 250);
+        assert_eq!(rejected, 3);
+
+        let mut window = BackfillWindow::new(2);
+        assert!(window.shrink());
+        assert!(!window.shrink());
+
+        assert!(is_range_too_large(
+            "server returned an error response: error code -32005: query returned more than 10000 results"
+        ));
+        assert!(is_range_too_large("block range is too wide"));
+        assert!(is_range_too_large("log response size exceeded"));
+        assert!(!is_range_too_large("rate limit exceeded"));
+        assert!(!is_range_too_large("429 Too Many Requests"));
+        assert!(!is_range_too_large(
+            "error code -32005: too many requests, rate limit exceeded"
+        ));
+        assert!(!is_range_too_large("connection reset"));
+    }
+}
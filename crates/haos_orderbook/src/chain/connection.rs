@@ -0,0 +1,135 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+/// State of the listener's connection to the chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Backfilling before the first subscription.
+    #[default]
+    Connecting,
+    Connected,
+    /// The subscription ended or fetching blocks failed, and is being restored.
+    Reconnecting {
+        attempts: u32,
+    },
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Reconnecting { attempts } => {
+                write!(f, "reconnecting after {} attempts", attempts)
+            }
+        }
+    }
+}
+
+/// What the listener last heard from the chain, to tell a stalled stream
+/// from a quiet market.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    /// Latest block header received.
+    pub latest_block: u64,
+    /// When the latest block header was received.
+    pub last_header_at: Option<SystemTime>,
+    /// When the stream was last connected.
+    pub connected_at: Option<SystemTime>,
+}
+
+impl ConnectionStatus {
+    /// When a connected stream counts as stalled unless another header
+    /// arrives, None while it is not connected.
+    pub fn stalls_at(&self, timeout: Duration) -> Option<SystemTime> {
+        if self.state != ConnectionState::Connected {
+            return None;
+        }
+        // a stream that never delivered a header stalls after connecting
+        self.last_header_at
+            .max(self.connected_at)
+            .map(|at| at + timeout)
+    }
+
+    /// Whether a connected stream received no header for longer than
+    /// `timeout` at `now`. A quiet market still receives headers.
+    pub fn is_stalled(&self, timeout: Duration, now: SystemTime) -> bool {
+        self.stalls_at(timeout).is_some_and(|at| now > at)
+    }
+}
+
+/// Exponential backoff between reconnection attempts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempts: 0,
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Delay before the next attempt, doubling up to the maximum.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(2_u32.saturating_pow(self.attempts))
+            .min(self.max);
+        self.attempts += 1;
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::chain::connection::{Backoff, ConnectionState, ConnectionStatus};
+
+    #[test]
+    fn test_backoff_and_stall() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let delays = (0..5).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 8, 10].map(Duration::from_secs));
+        assert_eq!(backoff.attempts(), 5);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+
+        let now = SystemTime::now();
+        let mut status = ConnectionStatus {
+            state: ConnectionState::Connected,
+            latest_block: 10,
+            last_header_at: Some(now - Duration::from_secs(30)),
+            connected_at: Some(now - Duration::from_secs(40)),
+        };
+        assert!(status.is_stalled(Duration::from_secs(20), now));
+        assert!(!status.is_stalled(Duration::from_secs(60), now));
+        // reconnecting restarts the wait for a header
+        status.connected_at = Some(now - Duration::from_secs(10));
+        assert!(!status.is_stalled(Duration::from_secs(20), now));
+        // as does connecting, even if no header ever arrives
+        status.last_header_at = None;
+        status.connected_at = Some(now - Duration::from_secs(30));
+        assert!(status.is_stalled(Duration::from_secs(20), now));
+        // a reconnecting stream is not stalled, it is already being restored
+        status.state = ConnectionState::Reconnecting { attempts: 1 };
+        assert!(!status.is_stalled(Duration::from_secs(20), now));
+    }
+}
@@ -1,7 +1,16 @@
-use alloy::sol;
+use alloy::{primitives::B256, sol, sol_types::SolEvent};
 
 sol! {
     #[sol(rpc)]
     IOrderBook,
     "orderbook.abi"
 }
+
+/// Signatures of the events the listener handles, logs are filtered on them.
+/// The contracts log no lifecycle events, markets change state through the
+/// admin endpoint.
+pub const EVENT_SIGNATURES: [B256; 3] = [
+    IOrderBook::OrderPlaced::SIGNATURE_HASH,
+    IOrderBook::OrderFilled::SIGNATURE_HASH,
+    IOrderBook::OrdersMatched::SIGNATURE_HASH,
+];
@@ -1,36 +1,119 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    time::{Duration, SystemTime},
+};
+
 use alloy::{
-    primitives::Address,
+    primitives::{Address, Bloom, BloomInput, B256},
     providers::Provider,
-    pubsub::PubSubFrontend,
-    rpc::types::{Filter, Log},
+    pubsub::{Subscription, SubscriptionStream},
+    rpc::types::{BlockNumberOrTag, BlockTransactionsKind, Filter, Header, Log},
     sol_types::SolEvent,
+    transports::Transport,
 };
 use anyhow::Result;
-use futures_util::{future::join_all, StreamExt};
-use tracing::info;
+use futures_util::{
+    future::{join_all, try_join_all, BoxFuture},
+    StreamExt,
+};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use super::{
+    backfill::{is_range_too_large, BackfillWindow},
+    connection::{Backoff, ConnectionState, ConnectionStatus},
+    contract::{IOrderBook, EVENT_SIGNATURES},
+    reorg::BlockHistory,
+    ContractEvent, LogPosition,
+};
+use crate::{
+    checkpoint::{Checkpoint, CheckpointStore},
+    constants::{BACKFILL_WINDOW, MAX_RECONNECT_DELAY, RECONNECT_DELAY, REORG_DEPTH},
+    orderbook::{types::OrderId, MatchedOrders},
+    OrderHandler,
+};
+
+/// Opens a new provider when the current one cannot subscribe anymore.
+pub type Connector<P> = Box<dyn Fn() -> BoxFuture<'static, Result<P>> + Send + Sync>;
 
-use super::{contract::IOrderBook, ContractEvent};
-use crate::{orderbook::MatchedOrders, OrderHandler};
+// handlers events are delivered to, the tentative ones as soon as they are
+// logged and the final ones once they have enough confirmations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stream {
+    Tentative,
+    Final,
+}
+
+/// Where new blocks are learned from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlockSource {
+    /// A `newHeads` subscription, the provider needs pubsub support.
+    #[default]
+    Subscription,
+    /// The head block polled at an interval, works over any transport.
+    Polling(Duration),
+    /// A `newHeads` subscription along with one to the markets' logs, so
+    /// that the logs of each block do not have to be fetched.
+    LogSubscription,
+}
 
-pub struct OrderListenerBuilder<'a, P: Provider<PubSubFrontend>, H: OrderHandler> {
+pub struct OrderListenerBuilder<'a, P: Provider<T>, T: Transport + Clone, H: OrderHandler> {
     provider: &'a P,
-    address: Option<Address>,
+    block_source: BlockSource,
+    addresses: Vec<Address>,
     handlers: Vec<H>,
+    tentative_handlers: Vec<H>,
     start_block: u64,
+    reorg_depth: usize,
+    confirmations: u64,
+    backfill_window: u64,
+    connector: Option<Connector<P>>,
+    reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+    stall_timeout: Option<Duration>,
+    checkpoints: Option<CheckpointStore>,
+    _transport: PhantomData<T>,
 }
 
-impl<'a, P: Provider<PubSubFrontend>, H: OrderHandler> OrderListenerBuilder<'a, P, H> {
+impl<'a, P: Provider<T>, T: Transport + Clone, H: OrderHandler> OrderListenerBuilder<'a, P, T, H> {
     pub fn new(provider: &'a P) -> Self {
         Self {
             provider,
-            address: None,
+            block_source: BlockSource::default(),
+            addresses: Vec::new(),
             handlers: Vec::new(),
+            tentative_handlers: Vec::new(),
             start_block: 1,
+            reorg_depth: REORG_DEPTH,
+            confirmations: 0,
+            backfill_window: BACKFILL_WINDOW,
+            connector: None,
+            reconnect_delay: RECONNECT_DELAY,
+            max_reconnect_delay: MAX_RECONNECT_DELAY,
+            stall_timeout: None,
+            checkpoints: None,
+            _transport: PhantomData,
         }
     }
 
+    /// Streams the markets' logs along with new blocks instead of fetching
+    /// the logs of every block.
+    pub fn with_log_streaming(mut self) -> Self {
+        self.block_source = BlockSource::LogSubscription;
+        self
+    }
+
+    /// Polls for new blocks instead of subscribing, for endpoints without
+    /// WebSocket support.
+    pub fn with_polling(mut self, interval: Duration) -> Self {
+        self.block_source = BlockSource::Polling(interval);
+        self
+    }
+
+    /// Adds an OrderBook contract to listen to, can be called once per market.
     pub fn with_address(mut self, address: Address) -> Self {
-        self.address = Some(address);
+        self.addresses.push(address);
         self
     }
 
@@ -39,144 +122,1452 @@ impl<'a, P: Provider<PubSubFrontend>, H: OrderHandler> OrderListenerBuilder<'a,
         self
     }
 
+    /// Number of recent blocks a reorg can be undone in.
+    pub fn with_reorg_depth(mut self, depth: usize) -> Self {
+        self.reorg_depth = depth;
+        self
+    }
+
+    /// Number of blocks an event must be buried under before it is handed
+    /// to the handlers.
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// Largest number of blocks logs are fetched for at once, the window
+    /// shrinks below it when the provider rejects a range.
+    pub fn with_backfill_window(mut self, blocks: u64) -> Self {
+        self.backfill_window = blocks;
+        self
+    }
+
+    /// Opens a fresh provider when the block subscription cannot be
+    /// restored on the current one.
+    pub fn with_connector(mut self, connector: Connector<P>) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    /// Delay before the first attempt to restore the block subscription or
+    /// polling, doubled on every failed attempt up to `max_delay`.
+    pub fn with_reconnect_backoff(mut self, delay: Duration, max_delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self.max_reconnect_delay = max_delay;
+        self
+    }
+
+    /// Restores the block subscription once it delivered no header for
+    /// longer than `timeout`, as if it had failed.
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Records the last block every final handler finished with.
+    pub fn with_checkpoints(mut self, store: CheckpointStore) -> Self {
+        self.checkpoints = Some(store);
+        self
+    }
+
+    /// Adds a handler that receives events once they are final.
     pub fn with_handler(mut self, handler: H) -> Self {
         self.handlers.push(handler);
         self
     }
 
-    pub fn build(self) -> Result<OrderListener<'a, P, H>> {
-        let address = self
-            .address
-            .ok_or_else(|| anyhow::anyhow!("Address is required for OrderListener"))?;
+    /// Adds a handler that receives events as soon as they are logged,
+    /// before they are final.
+    pub fn with_tentative_handler(mut self, handler: H) -> Self {
+        self.tentative_handlers.push(handler);
+        self
+    }
+
+    pub fn build(self) -> Result<OrderListener<'a, P, T, H>> {
+        if self.addresses.is_empty() {
+            return Err(anyhow::anyhow!("Address is required for OrderListener"));
+        }
 
         Ok(OrderListener {
             provider: self.provider,
-            address,
+            block_source: self.block_source,
+            reconnected: None,
+            connector: self.connector,
+            addresses: self.addresses,
             handlers: self.handlers,
+            tentative_handlers: self.tentative_handlers,
             start_block: self.start_block,
+            confirmations: self.confirmations,
+            window: BackfillWindow::new(self.backfill_window),
+            // blocks are kept at least until they are final
+            history: BlockHistory::new(
+                self.reorg_depth
+                    .max(usize::try_from(self.confirmations)? + 1),
+            ),
+            latest_block: 0,
+            final_block: 0,
+            reconnect_delay: self.reconnect_delay,
+            max_reconnect_delay: self.max_reconnect_delay,
+            stall_timeout: self.stall_timeout,
+            checkpoints: self.checkpoints,
+            streamed: None,
+            streamed_from: 0,
+            status: watch::Sender::new(ConnectionStatus::default()),
+            _transport: PhantomData,
         })
     }
 }
 
-pub struct OrderListener<'a, P: Provider<PubSubFrontend>, H: OrderHandler> {
+pub struct OrderListener<'a, P: Provider<T>, T: Transport + Clone, H: OrderHandler> {
     provider: &'a P,
-    address: Address,
+    block_source: BlockSource,
+    // provider opened by the connector after the first one failed
+    reconnected: Option<P>,
+    connector: Option<Connector<P>>,
+    addresses: Vec<Address>,
     handlers: Vec<H>,
+    tentative_handlers: Vec<H>,
     start_block: u64,
+    confirmations: u64,
+    window: BackfillWindow,
+    // recent blocks and their events, to roll back orphaned ones and hand
+    // them over once they are final
+    history: BlockHistory,
+    // latest block handed to the tentative and to the final handlers
+    latest_block: u64,
+    final_block: u64,
+    reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+    stall_timeout: Option<Duration>,
+    status: watch::Sender<ConnectionStatus>,
+    checkpoints: Option<CheckpointStore>,
+    // streamed logs by block until the block's header arrives, None while
+    // logs are not streamed
+    streamed: Option<BTreeMap<u64, Vec<Log>>>,
+    // first block whose logs are all streamed, older ones are fetched
+    streamed_from: u64,
+    _transport: PhantomData<T>,
 }
 
-impl<'a, P: Provider<PubSubFrontend>, H: OrderHandler> OrderListener<'a, P, H> {
-    pub fn builder(provider: &'a P) -> OrderListenerBuilder<'a, P, H> {
+// what a new block brings, fetched before any of it is handed over
+#[derive(Debug)]
+struct BlockUpdate {
+    // newest block shared with the blocks handled before
+    fork: u64,
+    // canonical blocks after the fork, up to the new one
+    blocks: Vec<(u64, B256)>,
+    orders: Vec<ContractEvent>,
+}
+
+impl<'a, P: Provider<T>, T: Transport + Clone, H: OrderHandler> OrderListener<'a, P, T, H> {
+    pub fn builder(provider: &'a P) -> OrderListenerBuilder<'a, P, T, H> {
         OrderListenerBuilder::new(provider)
     }
 
+    /// Follows the state of the block subscription while listening.
+    pub fn connection_status(&self) -> watch::Receiver<ConnectionStatus> {
+        self.status.subscribe()
+    }
+
     pub async fn listen(&mut self) -> Result<()> {
-        let mut latest_block = self.provider.get_block_number().await?;
-        info!("Latest block: {}", latest_block);
+        self.backfill().await?;
+        match self.block_source {
+            BlockSource::Subscription | BlockSource::LogSubscription => {
+                self.follow_subscription().await
+            }
+            BlockSource::Polling(interval) => self.poll(interval).await,
+        }
+    }
 
+    // hand the events logged since the start block to the handlers, retrying
+    // with backoff while the endpoint fails
+    async fn backfill(&mut self) -> Result<()> {
+        let mut backoff = Backoff::new(self.reconnect_delay, self.max_reconnect_delay);
+        let (orders, kept) = loop {
+            match self.fetch_backfill().await {
+                Ok(backfill) => break backfill,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    warn!("Failed to backfill blocks, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        };
+        info!("Latest block: {}", self.latest_block);
+
+        // the latest blocks are kept, to hand their events to the final
+        // handlers later and to undo them on a reorg
+        let latest_block = self.latest_block;
+        for (number, hash) in kept {
+            self.history.push_block(number, hash);
+        }
+        self.history.push_events(&orders);
+        if !self.tentative_handlers.is_empty() {
+            self.handle_orders(Stream::Tentative, &orders).await?;
+            self.blocks_handled(Stream::Tentative, latest_block).await?;
+        }
+        self.final_block = latest_block
+            .saturating_sub(self.confirmations)
+            .max(self.start_block.saturating_sub(1));
+        let final_orders = orders
+            .into_iter()
+            .filter(|order| order.position().block_number <= self.final_block)
+            .collect();
+        self.handle_orders(Stream::Final, &final_orders).await?;
+        self.blocks_handled(Stream::Final, self.final_block).await?;
+        self.save_checkpoint();
+        Ok(())
+    }
+
+    // the events since the start block and the hashes of the blocks to keep
+    async fn fetch_backfill(&mut self) -> Result<(Vec<ContractEvent>, Vec<(u64, B256)>)> {
+        self.latest_block = self.provider().get_block_number().await?;
+        let latest_block = self.latest_block;
         let orders = self
             .fetch_orders_in_range(self.start_block, latest_block)
             .await?;
-        self.handle_orders(&orders).await?;
+        let kept = latest_block.saturating_sub(self.history.depth() as u64 - 1)..=latest_block;
+        let hashes = try_join_all(kept.clone().map(|number| self.block_hash(number))).await?;
+        Ok((orders, kept.zip(hashes).collect()))
+    }
+
+    async fn follow_subscription(&mut self) -> Result<()> {
+        // failed subscriptions and fetches share one backoff, reset once
+        // blocks are handled again
+        let mut backoff = Backoff::new(self.reconnect_delay, self.max_reconnect_delay);
+        let mut reconnecting = false;
+        loop {
+            let mut stream = self.subscribe(&mut backoff).await.into_stream();
+            // logs are subscribed to once blocks are, on the same provider,
+            // the catch-up fetches the blocks logged before
+            let mut logs = match self.block_source {
+                BlockSource::LogSubscription => self.subscribe_logs().await,
+                _ => None,
+            };
+            // catch up on the blocks logged while the subscription was down,
+            // or before the logs were streamed
+            let mut failure = None;
+            if reconnecting || logs.is_some() {
+                match self.catch_up().await {
+                    Ok(update) => self.apply_update(update).await?,
+                    Err(e) => failure = Some(e),
+                }
+            }
+            if failure.is_none() {
+                backoff.reset();
+                self.set_state(ConnectionState::Connected);
+            }
+            while failure.is_none() {
+                tokio::select! {
+                    block_header = stream.next() => match block_header {
+                        Some(block_header) => match self.fetch_update(&block_header).await {
+                            Ok(update) => self.apply_update(update).await?,
+                            Err(e) => failure = Some(e),
+                        },
+                        None => break,
+                    },
+                    log = next_log(&mut logs) => match log {
+                        Some(log) => {
+                            let Some(block_number) = self.buffer_log(log)? else {
+                                continue;
+                            };
+                            match self.refetch_from(block_number).await {
+                                Ok(update) => self.apply_update(Some(update)).await?,
+                                // the catch-up fetches the rolled back blocks again
+                                Err(e) => {
+                                    self.roll_back(block_number - 1).await?;
+                                    failure = Some(e);
+                                }
+                            }
+                        }
+                        None => {
+                            warn!("Log subscription ended, fetching logs per block");
+                            logs = None;
+                            self.streamed = None;
+                        }
+                    },
+                    _ = stalled(self.stalls_at()) => {
+                        failure = Some(anyhow::anyhow!(
+                            "No block header for over {:?}",
+                            self.stall_timeout.unwrap_or_default()
+                        ));
+                    }
+                }
+            }
+            match failure {
+                // the connection may be gone for good, subscribe again
+                Some(e) => {
+                    let delay = backoff.next_delay();
+                    self.set_state(ConnectionState::Reconnecting {
+                        attempts: backoff.attempts(),
+                    });
+                    warn!(
+                        "Failed to fetch blocks after block {}, reconnecting in {:?}: {}",
+                        self.latest_block, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                None => {
+                    warn!(
+                        "Block subscription ended after block {}, reconnecting",
+                        self.latest_block
+                    );
+                    self.set_state(ConnectionState::Reconnecting { attempts: 0 });
+                }
+            }
+            reconnecting = true;
+        }
+    }
 
-        let sub = self.provider.subscribe_blocks().await?;
-        let mut stream = sub.into_stream();
+    // the blocks up to the head, streamed logs cover the blocks after it
+    async fn catch_up(&mut self) -> Result<Option<BlockUpdate>> {
+        let head = self.latest_header().await?;
+        self.streamed_from = head.number + 1;
+        self.fetch_update(&head).await
+    }
 
-        while let Some(block_header) = stream.next().await {
-            // get logs from block
-            let block_number = block_header.number;
-            if latest_block + 1 > block_number {
-                continue;
+    // stream the markets' logs, falling back to fetching them per block
+    async fn subscribe_logs(&mut self) -> Option<SubscriptionStream<Log>> {
+        match self.provider().subscribe_logs(&self.filter()).await {
+            Ok(subscription) => {
+                self.streamed = Some(BTreeMap::new());
+                Some(subscription.into_stream())
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to subscribe to logs, fetching them per block: {}",
+                    e
+                );
+                self.streamed = None;
+                None
+            }
+        }
+    }
+
+    // buffer a streamed log until the header of its block arrives. Returns
+    // the block of a log that arrived after its block was handled without it
+    fn buffer_log(&mut self, log: Log) -> Result<Option<u64>> {
+        let Some(block_number) = log.block_number else {
+            return Ok(None);
+        };
+        if log.removed {
+            // the reorg itself is handled with the headers of the new chain
+            if let Some(logs) = self
+                .streamed
+                .as_mut()
+                .and_then(|streamed| streamed.get_mut(&block_number))
+            {
+                logs.retain(|kept| {
+                    (kept.block_hash, kept.log_index) != (log.block_hash, log.log_index)
+                });
+            }
+            return Ok(None);
+        }
+        if block_number > self.latest_block {
+            if let Some(streamed) = self.streamed.as_mut() {
+                streamed.entry(block_number).or_default().push(log);
             }
-            let orders = self
-                .fetch_orders_in_range(latest_block + 1, block_number)
-                .await?;
-            latest_block = block_number;
-            self.handle_orders(&orders).await?;
+            return Ok(None);
+        }
+        // logs of fetched or orphaned blocks were already handled or never will be
+        if block_number < self.streamed_from || self.history.hash(block_number) != log.block_hash {
+            return Ok(None);
         }
+        let Some(event) = self.extract_order_from_log(log)? else {
+            return Ok(None);
+        };
+        if self
+            .history
+            .events(block_number, block_number)
+            .iter()
+            .any(|kept| kept.position() == event.position())
+        {
+            return Ok(None);
+        }
+        warn!(
+            "Log at {:?} arrived after its block was handled, handling blocks {} to {} again",
+            event.position(),
+            block_number,
+            self.latest_block
+        );
+        Ok(Some(block_number))
+    }
+
+    // the handled blocks from `block_number` on, fetched again so that a
+    // late log is handed over in the order it was logged
+    async fn refetch_from(&mut self, block_number: u64) -> Result<BlockUpdate> {
+        let blocks = (block_number..=self.latest_block)
+            .filter_map(|number| self.history.hash(number).map(|hash| (number, hash)))
+            .collect();
+        let orders = self
+            .fetch_orders_in_range(block_number, self.latest_block)
+            .await?;
+        Ok(BlockUpdate {
+            fork: block_number - 1,
+            blocks,
+            orders,
+        })
+    }
 
+    // the streamed logs of a new block, None if they have to be fetched
+    fn take_streamed(
+        &mut self,
+        fork: u64,
+        block_header: &Header,
+    ) -> Result<Option<Vec<ContractEvent>>> {
+        let Some(streamed) = self.streamed.as_mut() else {
+            return Ok(None);
+        };
+        let logs = streamed.remove(&block_header.number).unwrap_or_default();
+        streamed.retain(|number, _| *number > block_header.number);
+        // without confirmations the block is final at once, and the stream
+        // may not have delivered all of its logs yet
+        if self.confirmations == 0 && !self.handlers.is_empty() {
+            return Ok(None);
+        }
+        // logs of replaced or skipped blocks may be missing from the stream
+        if fork != self.latest_block
+            || block_header.number != fork + 1
+            || block_header.number < self.streamed_from
+        {
+            return Ok(None);
+        }
+        let mut logs = logs
+            .into_iter()
+            .filter(|log| log.block_hash == Some(block_header.hash))
+            .collect::<Vec<_>>();
+        // a header may arrive before its logs, only a bloom without the
+        // markets' events proves that the block logged none
+        if logs.is_empty() && self.may_have_logged(&block_header.logs_bloom) {
+            return Ok(None);
+        }
+        logs.sort_by_key(|log| log.log_index);
+        let mut orders = Vec::new();
+        for log in logs {
+            if let Some(order) = self.extract_order_from_log(log)? {
+                orders.push(order);
+            }
+        }
+        Ok(Some(orders))
+    }
+
+    // poll the head block instead of subscribing, retrying with backoff
+    // while the endpoint fails to return it or its blocks
+    async fn poll(&mut self, interval: Duration) -> Result<()> {
+        let mut backoff = Backoff::new(self.reconnect_delay, self.max_reconnect_delay);
+        loop {
+            match self.catch_up().await {
+                Ok(update) => {
+                    backoff.reset();
+                    self.set_state(ConnectionState::Connected);
+                    self.apply_update(update).await?;
+                    tokio::time::sleep(interval).await;
+                }
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    self.set_state(ConnectionState::Reconnecting {
+                        attempts: backoff.attempts(),
+                    });
+                    warn!("Failed to poll blocks, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    async fn latest_header(&self) -> Result<Header> {
+        let block = self
+            .provider()
+            .get_block_by_number(BlockNumberOrTag::Latest, BlockTransactionsKind::Hashes)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Latest block not found"))?;
+        Ok(block.header)
+    }
+
+    fn provider(&self) -> &P {
+        self.reconnected.as_ref().unwrap_or(self.provider)
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.status.send_if_modified(|status| {
+            if status.state == state {
+                return false;
+            }
+            info!("Connection to the chain is {}", state);
+            status.state = state;
+            if state == ConnectionState::Connected {
+                status.connected_at = Some(SystemTime::now());
+            }
+            true
+        });
+    }
+
+    // when the block subscription counts as stalled, None if it cannot
+    fn stalls_at(&self) -> Option<SystemTime> {
+        self.stall_timeout
+            .and_then(|timeout| self.status.borrow().stalls_at(timeout))
+    }
+
+    // subscribe to new blocks, retrying with backoff until it succeeds
+    async fn subscribe(&mut self, backoff: &mut Backoff) -> Subscription<Header> {
+        loop {
+            match self.provider().subscribe_blocks().await {
+                Ok(subscription) => return subscription,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    self.set_state(ConnectionState::Reconnecting {
+                        attempts: backoff.attempts(),
+                    });
+                    warn!(
+                        "Failed to subscribe to blocks, retrying in {:?}: {}",
+                        delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            // the current provider may be closed for good, open a new one
+            if let Some(connector) = &self.connector {
+                match connector().await {
+                    Ok(provider) => self.reconnected = Some(provider),
+                    Err(e) => warn!("Failed to open a new provider: {}", e),
+                }
+            }
+        }
+    }
+
+    // fetch what a new block brings without handing anything over yet, so
+    // that a failed fetch can be retried. None if the block is known already
+    async fn fetch_update(&mut self, block_header: &Header) -> Result<Option<BlockUpdate>> {
+        let block_number = block_header.number;
+        self.status.send_modify(|status| {
+            status.latest_block = status.latest_block.max(block_number);
+            status.last_header_at = Some(SystemTime::now());
+        });
+        if self.history.hash(block_number) == Some(block_header.hash) {
+            return Ok(None);
+        }
+        // a block that does not extend the kept chain replaced some of it,
+        // or follows blocks that were never received
+        let (fork, mut blocks) = if self.history.extends(block_number, block_header.parent_hash) {
+            (self.latest_block, Vec::new())
+        } else {
+            self.find_fork(block_number, block_header.parent_hash)
+                .await?
+        };
+        if fork < self.latest_block {
+            warn!(
+                "Reorg after block {}, blocks {} to {} were replaced",
+                fork,
+                fork + 1,
+                self.latest_block
+            );
+        }
+        blocks.push((block_number, block_header.hash));
+
+        // get logs from the canonical blocks, unless they were streamed
+        let orders = match self.take_streamed(fork, block_header)? {
+            Some(mut orders) => {
+                self.stamp_matches(&mut orders, Some(block_header)).await?;
+                orders
+            }
+            None => self.fetch_orders_in_range(fork + 1, block_number).await?,
+        };
+        Ok(Some(BlockUpdate {
+            fork,
+            blocks,
+            orders,
+        }))
+    }
+
+    // roll back the orphaned blocks, then hand the new blocks to the
+    // tentative handlers and the blocks that became final to the final ones
+    async fn apply_update(&mut self, update: Option<BlockUpdate>) -> Result<()> {
+        let Some(BlockUpdate {
+            fork,
+            blocks,
+            orders,
+        }) = update
+        else {
+            return Ok(());
+        };
+        if fork < self.latest_block {
+            self.roll_back(fork).await?;
+        }
+
+        // the kept events of blocks that were not final yet and the new ones
+        // that are final already, before new blocks push old ones out
+        let block_number = blocks.last().map_or(fork, |(number, _)| *number);
+        let target = block_number.saturating_sub(self.confirmations);
+        let final_block = self.final_block;
+        let mut final_orders = self.history.events(final_block + 1, target.min(fork));
+        final_orders.extend(
+            orders
+                .iter()
+                .filter(|order| (final_block + 1..=target).contains(&order.position().block_number))
+                .cloned(),
+        );
+        for (number, hash) in blocks {
+            self.history.push_block(number, hash);
+        }
+        self.history.push_events(&orders);
+        self.latest_block = block_number;
+        if !self.tentative_handlers.is_empty() {
+            self.handle_orders(Stream::Tentative, &orders).await?;
+            self.blocks_handled(Stream::Tentative, block_number).await?;
+        }
+        if target > final_block {
+            self.handle_orders(Stream::Final, &final_orders).await?;
+            self.blocks_handled(Stream::Final, target).await?;
+            self.final_block = target;
+            self.save_checkpoint();
+        }
+        Ok(())
+    }
+
+    // undo the events of the blocks after `fork` in the handlers that saw them
+    async fn roll_back(&mut self, fork: u64) -> Result<()> {
+        let orphaned = self.history.rollback(fork);
+        warn!(
+            "Rolling back {} events of blocks {} to {}",
+            orphaned.len(),
+            fork + 1,
+            self.latest_block
+        );
+        self.rollback(Stream::Tentative, fork, orphaned.clone())
+            .await?;
+        // final handlers only saw the events of final blocks
+        if fork < self.final_block {
+            warn!(
+                "Rolling back blocks after block {}, deeper than {} confirmations",
+                fork, self.confirmations
+            );
+            let final_block = self.final_block;
+            let orphaned = orphaned
+                .into_iter()
+                .filter(|event| event.position().block_number <= final_block)
+                .collect();
+            self.rollback(Stream::Final, fork, orphaned).await?;
+            self.final_block = fork;
+            self.save_checkpoint();
+        }
+        self.latest_block = fork;
         Ok(())
     }
 
+    // a failed checkpoint only costs a longer backfill, keep listening
+    fn save_checkpoint(&self) {
+        let Some(store) = &self.checkpoints else {
+            return;
+        };
+        let checkpoint = Checkpoint {
+            block_number: self.final_block,
+            addresses: self.addresses.clone(),
+        };
+        if let Err(e) = store.save(&checkpoint) {
+            error!(
+                "Failed to save checkpoint at block {}: {:?}",
+                self.final_block, e
+            );
+        }
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<B256> {
+        let block = self
+            .provider()
+            .get_block_by_number(number.into(), BlockTransactionsKind::Hashes)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Block {} not found", number))?;
+        Ok(block.header.hash)
+    }
+
+    // walk back from a new block to the newest block it shares with the kept
+    // chain, returning that block and the canonical blocks after it. Blocks
+    // never received are skipped by checking the kept head is still canonical.
+    // A reorg that replaced every kept block re-syncs from the one before them
+    async fn find_fork(&self, number: u64, parent_hash: B256) -> Result<(u64, Vec<(u64, B256)>)> {
+        let mut blocks = Vec::new();
+        let (mut number, mut hash) = (number.saturating_sub(1), parent_hash);
+        if number > self.latest_block {
+            number = self.latest_block;
+            hash = self.block_hash(number).await?;
+        }
+        loop {
+            if self.history.hash(number) == Some(hash) {
+                blocks.reverse();
+                return Ok((number, blocks));
+            }
+            blocks.push((number, hash));
+            match self.history.oldest() {
+                Some(oldest) if number > oldest => {}
+                _ => {
+                    // events of older blocks cannot be undone anymore
+                    error!(
+                        "Reorg at block {} is deeper than the {} blocks kept, re-syncing from block {}",
+                        number,
+                        self.history.depth(),
+                        number.saturating_sub(1)
+                    );
+                    blocks.reverse();
+                    return Ok((number.saturating_sub(1), blocks));
+                }
+            }
+            let block = self
+                .provider()
+                .get_block_by_hash(hash, BlockTransactionsKind::Hashes)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Block {} not found", hash))?;
+            hash = block.header.parent_hash;
+            number -= 1;
+        }
+    }
+
     fn extract_order_from_log(&self, log: Log) -> Result<Option<ContractEvent>> {
-        let block_number = log.block_number.unwrap_or(0);
+        let position = LogPosition::of(&log);
+        let market = log.address();
 
         match log.topic0() {
             Some(&IOrderBook::OrderPlaced::SIGNATURE_HASH) => {
                 let IOrderBook::OrderPlaced { id } = log.log_decode()?.inner.data;
-                Ok(Some(ContractEvent::OrderUpdated(id, block_number)))
+                Ok(Some(ContractEvent::OrderUpdated(
+                    market,
+                    id.into(),
+                    position,
+                )))
             }
             Some(&IOrderBook::OrderFilled::SIGNATURE_HASH) => {
                 let IOrderBook::OrderFilled { id } = log.log_decode()?.inner.data;
-                Ok(Some(ContractEvent::OrderUpdated(id, block_number)))
+                Ok(Some(ContractEvent::OrderUpdated(
+                    market,
+                    id.into(),
+                    position,
+                )))
             }
             Some(&IOrderBook::OrdersMatched::SIGNATURE_HASH) => {
                 let IOrderBook::OrdersMatched { takerId, makerId } = log.log_decode()?.inner.data;
+                // stamped with the block's timestamp later if the log lacks it
                 Ok(Some(ContractEvent::OrdersMatched(
-                    takerId,
-                    makerId,
-                    block_number,
+                    market,
+                    takerId.into(),
+                    makerId.into(),
+                    position,
+                    log.block_timestamp.unwrap_or_default(),
                 )))
             }
             _ => Ok(None),
         }
     }
 
+    // logs of the markets' known events, other logs are never fetched
+    fn filter(&self) -> Filter {
+        Filter::new()
+            .address(self.addresses.clone())
+            .event_signature(EVENT_SIGNATURES.to_vec())
+    }
+
+    // whether a block with `bloom` may have logged one of the markets' events
+    fn may_have_logged(&self, bloom: &Bloom) -> bool {
+        self.addresses
+            .iter()
+            .any(|address| bloom.contains_input(BloomInput::Raw(address.as_slice())))
+            && EVENT_SIGNATURES
+                .iter()
+                .any(|signature| bloom.contains_input(BloomInput::Raw(signature.as_slice())))
+    }
+
+    // page through the range in windows the provider accepts
     async fn fetch_orders_in_range(
-        &self,
+        &mut self,
         from_block: u64,
         to_block: u64,
     ) -> Result<Vec<ContractEvent>> {
-        let filter = Filter::new()
-            .address(self.address)
-            .from_block(from_block)
-            .to_block(to_block);
-
-        let logs = self.provider.get_logs(&filter).await?;
-
+        let report_progress = to_block.saturating_sub(from_block) >= self.window.size();
         let mut orders = Vec::new();
-        for log in logs {
-            if let Some(order) = self.extract_order_from_log(log)? {
-                orders.push(order);
+        let mut from = from_block;
+        while from <= to_block {
+            let to = self.window.chunk_end(from, to_block);
+            let filter = self.filter().from_block(from).to_block(to);
+
+            let logs = match self.provider().get_logs(&filter).await {
+                Ok(logs) => logs,
+                Err(e) if is_range_too_large(&e.to_string()) && self.window.shrink() => {
+                    warn!(
+                        "Blocks {} to {} rejected, fetching {} blocks at once: {}",
+                        from,
+                        to,
+                        self.window.size(),
+                        e
+                    );
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            self.window.record(logs.len());
+            for log in logs {
+                if let Some(order) = self.extract_order_from_log(log)? {
+                    orders.push(order);
+                }
+            }
+            if report_progress {
+                info!(
+                    "Fetched blocks {} to {} of {}, {} events so far",
+                    from_block,
+                    to,
+                    to_block,
+                    orders.len()
+                );
             }
+            from = to + 1;
         }
 
+        self.stamp_matches(&mut orders, None).await?;
         Ok(orders)
     }
 
-    async fn handle_orders(&mut self, orders: &Vec<ContractEvent>) -> Result<()> {
-        info!("Handling orders: {:?}", orders);
+    // matches are priced with the timestamp of their block, which logs do
+    // not always carry
+    async fn stamp_matches(
+        &self,
+        orders: &mut [ContractEvent],
+        block_header: Option<&Header>,
+    ) -> Result<()> {
+        let mut timestamps = HashMap::new();
+        if let Some(block_header) = block_header {
+            timestamps.insert(block_header.number, block_header.timestamp);
+        }
+        for order in orders.iter_mut() {
+            let ContractEvent::OrdersMatched(.., position, timestamp) = order else {
+                continue;
+            };
+            if *timestamp != 0 {
+                continue;
+            }
+            let number = position.block_number;
+            if let Some(known) = timestamps.get(&number) {
+                *timestamp = *known;
+                continue;
+            }
+            let block = self
+                .provider()
+                .get_block_by_number(number.into(), BlockTransactionsKind::Hashes)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Block {} not found", number))?;
+            timestamps.insert(number, block.header.timestamp);
+            *timestamp = block.header.timestamp;
+        }
+        Ok(())
+    }
+
+    fn handlers(&mut self, stream: Stream) -> &mut Vec<H> {
+        match stream {
+            Stream::Tentative => &mut self.tentative_handlers,
+            Stream::Final => &mut self.handlers,
+        }
+    }
+
+    async fn rollback(
+        &mut self,
+        stream: Stream,
+        block_number: u64,
+        orphaned: Vec<ContractEvent>,
+    ) -> Result<()> {
+        for handler in self.handlers(stream).iter_mut() {
+            handler.rollback(block_number, orphaned.clone()).await?;
+        }
+        Ok(())
+    }
+
+    async fn blocks_handled(&mut self, stream: Stream, block_number: u64) -> Result<()> {
+        for handler in self.handlers(stream).iter_mut() {
+            handler.blocks_handled(block_number).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_orders(&mut self, stream: Stream, orders: &Vec<ContractEvent>) -> Result<()> {
+        info!("Handling {:?} orders: {:?}", stream, orders);
         for order in orders.iter() {
-            match order {
-                ContractEvent::OrdersMatched(taker_id, maker_id, _) => {
-                    let matched_orders = MatchedOrders {
-                        taker_order_id: taker_id.clone().try_into().unwrap(),
-                        maker_order_id: maker_id.clone().try_into().unwrap(),
-                    };
-                    join_all(
-                        self.handlers
-                            .iter_mut()
-                            .map(|handler| handler.match_orders(matched_orders.clone())),
-                    )
-                    .await
-                    .into_iter()
-                    .collect::<Result<Vec<_>, _>>()?;
-                }
-                _ => {}
+            if let ContractEvent::OrdersMatched(market, taker_id, maker_id, position, timestamp) =
+                order
+            {
+                let matched_orders = MatchedOrders::new(*taker_id, *maker_id);
+                join_all(self.handlers(stream).iter_mut().map(|handler| {
+                    handler.match_orders(*market, matched_orders.clone(), *position, *timestamp)
+                }))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?;
             }
         }
 
         let orders = orders
             .iter()
             .filter_map(|order| match order {
-                ContractEvent::OrderUpdated(id, block_number) => Some((*id, *block_number)),
+                ContractEvent::OrderUpdated(market, id, position) => {
+                    Some((*market, *id, *position))
+                }
                 _ => None,
             })
-            .collect::<Vec<_>>();
+            .collect::<Vec<(Address, OrderId, LogPosition)>>();
 
-        for handler in self.handlers.iter_mut() {
+        for handler in self.handlers(stream).iter_mut() {
             handler.handle_orders(orders.clone()).await?;
         }
         Ok(())
     }
 }
+
+// resolves once the block subscription stalled, never without a timeout
+async fn stalled(at: Option<SystemTime>) {
+    match at {
+        Some(at) => {
+            let delay = at.duration_since(SystemTime::now()).unwrap_or_default();
+            tokio::time::sleep(delay).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// next streamed log, never resolving without a log subscription
+async fn next_log(logs: &mut Option<SubscriptionStream<Log>>) -> Option<Log> {
+    match logs {
+        Some(logs) => logs.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use alloy::{
+        primitives::{keccak256, Address, BlockHash, BlockNumber, Bloom, B256, U256, U64},
+        providers::{Provider, ProviderCall, RootProvider},
+        rpc::{
+            client::NoParams,
+            types::{Block, BlockNumberOrTag, BlockTransactionsKind, Filter, Header, Log},
+        },
+        sol_types::SolEvent,
+        transports::{
+            http::{Client, Http},
+            TransportErrorKind, TransportResult,
+        },
+    };
+    use anyhow::Result;
+
+    use crate::{
+        chain::{contract::IOrderBook, listener::OrderListener, ContractEvent, LogPosition},
+        orderbook::{types::OrderId, MatchedOrders},
+        OrderHandler,
+    };
+
+    const MARKET: Address = Address::repeat_byte(7);
+
+    #[derive(Default)]
+    struct ChainState {
+        // canonical blocks, block `n` at index `n`
+        blocks: Vec<Block>,
+        // every block ever mined by hash, orphaned ones included
+        mined: HashMap<B256, Block>,
+        logs: HashMap<B256, Vec<Log>>,
+        // bumped on every reorg so that the new blocks get new hashes
+        fork: u64,
+        // requests to answer before failing one
+        failing_after: Option<usize>,
+        log_requests: usize,
+    }
+
+    impl ChainState {
+        fn fail(&mut self) -> TransportResult<()> {
+            match self.failing_after {
+                Some(0) => {
+                    self.failing_after = None;
+                    Err(TransportErrorKind::custom_str("connection reset"))
+                }
+                Some(requests) => {
+                    self.failing_after = Some(requests - 1);
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// A chain of blocks logging `OrderPlaced` events of one market.
+    #[derive(Clone)]
+    struct MockChain {
+        root: RootProvider<Http<Client>>,
+        state: Arc<Mutex<ChainState>>,
+    }
+
+    impl MockChain {
+        // a chain of `blocks` empty blocks after the genesis block
+        fn new(blocks: u64) -> Self {
+            let chain = Self {
+                root: RootProvider::new_http("http://localhost:8545".parse().unwrap()),
+                state: Arc::default(),
+            };
+            for _ in 0..=blocks {
+                chain.mine(&[]);
+            }
+            chain
+        }
+
+        // mine a block placing the orders `ids`, returning its header
+        fn mine(&self, ids: &[u32]) -> Header {
+            let mut state = self.state.lock().unwrap();
+            let number = state.blocks.len() as u64;
+            let parent_hash = state
+                .blocks
+                .last()
+                .map(|block| block.header.hash)
+                .unwrap_or_default();
+            let hash = keccak256(
+                [
+                    parent_hash.as_slice(),
+                    &number.to_be_bytes(),
+                    &state.fork.to_be_bytes(),
+                ]
+                .concat(),
+            );
+            let mut logs_bloom = Bloom::default();
+            let logs = ids
+                .iter()
+                .enumerate()
+                .map(|(index, id)| {
+                    let inner = alloy::primitives::Log {
+                        address: MARKET,
+                        data: IOrderBook::OrderPlaced {
+                            id: U256::from(*id),
+                        }
+                        .encode_log_data(),
+                    };
+                    logs_bloom.accrue_log(&inner);
+                    Log {
+                        inner,
+                        block_hash: Some(hash),
+                        block_number: Some(number),
+                        log_index: Some(index as u64),
+                        ..Default::default()
+                    }
+                })
+                .collect();
+            let header = Header {
+                hash,
+                inner: alloy::consensus::Header {
+                    number,
+                    parent_hash,
+                    timestamp: 1_000 + number * 12,
+                    logs_bloom,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let block = Block {
+                header: header.clone(),
+                ..Default::default()
+            };
+            state.blocks.push(block.clone());
+            state.mined.insert(hash, block);
+            state.logs.insert(hash, logs);
+            header
+        }
+
+        // drop the blocks from `number` on, the blocks mined next replace them
+        fn reorg(&self, number: u64) {
+            let mut state = self.state.lock().unwrap();
+            state.blocks.truncate(number as usize);
+            state.fork += 1;
+        }
+
+        // fail the request after the next `requests` ones
+        fn fail_after(&self, requests: usize) {
+            self.state.lock().unwrap().failing_after = Some(requests);
+        }
+
+        // the logs of canonical block `number`
+        fn logs(&self, number: u64) -> Vec<Log> {
+            let state = self.state.lock().unwrap();
+            state.logs[&state.blocks[number as usize].header.hash].clone()
+        }
+
+        fn log_requests(&self) -> usize {
+            self.state.lock().unwrap().log_requests
+        }
+
+        fn head(&self) -> Header {
+            let state = self.state.lock().unwrap();
+            state.blocks.last().unwrap().header.clone()
+        }
+
+        // the canonical orders up to block `to` by the block that placed them
+        fn orders(&self, to: u64) -> Vec<(u64, OrderId)> {
+            let state = self.state.lock().unwrap();
+            state
+                .blocks
+                .iter()
+                .take(to as usize + 1)
+                .flat_map(|block| &state.logs[&block.header.hash])
+                .map(|log| (log.block_number.unwrap(), order_id(log)))
+                .collect()
+        }
+    }
+
+    fn order_id(log: &Log) -> OrderId {
+        IOrderBook::OrderPlaced::decode_log_data(log.data(), true)
+            .unwrap()
+            .id
+            .into()
+    }
+
+    #[async_trait::async_trait]
+    impl Provider<Http<Client>> for MockChain {
+        fn root(&self) -> &RootProvider<Http<Client>> {
+            &self.root
+        }
+
+        fn get_block_number(&self) -> ProviderCall<Http<Client>, NoParams, U64, BlockNumber> {
+            let mut state = self.state.lock().unwrap();
+            let number = state.fail().map(|_| state.blocks.len() as u64 - 1);
+            ProviderCall::ready(number)
+        }
+
+        async fn get_block_by_hash(
+            &self,
+            hash: BlockHash,
+            _kind: BlockTransactionsKind,
+        ) -> TransportResult<Option<Block>> {
+            let mut state = self.state.lock().unwrap();
+            state.fail()?;
+            Ok(state.mined.get(&hash).cloned())
+        }
+
+        async fn get_block_by_number(
+            &self,
+            number: BlockNumberOrTag,
+            _kind: BlockTransactionsKind,
+        ) -> TransportResult<Option<Block>> {
+            let mut state = self.state.lock().unwrap();
+            state.fail()?;
+            Ok(match number {
+                BlockNumberOrTag::Number(number) => state.blocks.get(number as usize).cloned(),
+                _ => state.blocks.last().cloned(),
+            })
+        }
+
+        async fn get_logs(&self, filter: &Filter) -> TransportResult<Vec<Log>> {
+            let mut state = self.state.lock().unwrap();
+            state.fail()?;
+            state.log_requests += 1;
+            let from = filter.get_from_block().unwrap_or_default() as usize;
+            let to = filter.get_to_block().unwrap_or(u64::MAX) as usize;
+            Ok(state
+                .blocks
+                .iter()
+                .skip(from)
+                .take(to.saturating_sub(from).saturating_add(1))
+                .flat_map(|block| state.logs[&block.header.hash].clone())
+                .filter(|log| filter.address.matches(&log.address()))
+                .collect())
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorded {
+        // orders by the block that placed them, less the rolled back ones
+        orders: Vec<(u64, OrderId)>,
+        rollbacks: Vec<u64>,
+        handled: u64,
+    }
+
+    /// Records what the listener hands over, shared by its clones.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Recorded>>);
+
+    impl Recorder {
+        fn orders(&self) -> Vec<(u64, OrderId)> {
+            self.0.lock().unwrap().orders.clone()
+        }
+
+        fn rollbacks(&self) -> Vec<u64> {
+            self.0.lock().unwrap().rollbacks.clone()
+        }
+
+        fn handled(&self) -> u64 {
+            self.0.lock().unwrap().handled
+        }
+    }
+
+    impl OrderHandler for Recorder {
+        async fn handle_orders(
+            &mut self,
+            orders: Vec<(Address, OrderId, LogPosition)>,
+        ) -> Result<()> {
+            let mut recorded = self.0.lock().unwrap();
+            recorded.orders.extend(
+                orders
+                    .into_iter()
+                    .map(|(_, id, position)| (position.block_number, id)),
+            );
+            Ok(())
+        }
+
+        async fn match_orders(
+            &mut self,
+            _market: Address,
+            _orders: MatchedOrders,
+            _position: LogPosition,
+            _timestamp: u64,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn rollback(
+            &mut self,
+            block_number: u64,
+            _orphaned: Vec<ContractEvent>,
+        ) -> Result<()> {
+            let mut recorded = self.0.lock().unwrap();
+            recorded.orders.retain(|(block, _)| *block <= block_number);
+            recorded.rollbacks.push(block_number);
+            Ok(())
+        }
+
+        async fn blocks_handled(&mut self, block_number: u64) -> Result<()> {
+            self.0.lock().unwrap().handled = block_number;
+            Ok(())
+        }
+    }
+
+    type TestListener<'a> = OrderListener<'a, MockChain, Http<Client>, Recorder>;
+
+    fn build_listener(
+        chain: &MockChain,
+        reorg_depth: usize,
+        confirmations: u64,
+    ) -> (TestListener<'_>, Recorder, Recorder) {
+        let (tentative, handler) = (Recorder::default(), Recorder::default());
+        let listener = OrderListener::builder(chain)
+            .with_address(MARKET)
+            .with_reorg_depth(reorg_depth)
+            .with_confirmations(confirmations)
+            .with_tentative_handler(tentative.clone())
+            .with_handler(handler.clone())
+            .with_reconnect_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .build()
+            .unwrap();
+        (listener, tentative, handler)
+    }
+
+    // handle a new block as the block subscription does
+    async fn handle(listener: &mut TestListener<'_>, block_header: Header) {
+        let update = listener.fetch_update(&block_header).await.unwrap();
+        listener.apply_update(update).await.unwrap();
+    }
+
+    // handle a log as the log subscription does
+    async fn stream(listener: &mut TestListener<'_>, log: Log) {
+        if let Some(block_number) = listener.buffer_log(log).unwrap() {
+            let update = listener.refetch_from(block_number).await.unwrap();
+            listener.apply_update(Some(update)).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reorg_after_startup() {
+        let chain = MockChain::new(8);
+        chain.mine(&[1]);
+        chain.mine(&[2]);
+        let (mut listener, tentative, handler) = build_listener(&chain, 8, 0);
+        listener.backfill().await.unwrap();
+        assert_eq!(handler.orders(), chain.orders(10));
+
+        // blocks 9 and 10 are replaced right after startup
+        chain.reorg(9);
+        chain.mine(&[3]);
+        chain.mine(&[]);
+        handle(&mut listener, chain.mine(&[4])).await;
+        assert_eq!(tentative.rollbacks(), vec![8]);
+        assert_eq!(handler.rollbacks(), vec![8]);
+        assert_eq!(tentative.orders(), chain.orders(11));
+        assert_eq!(handler.orders(), chain.orders(11));
+        assert_eq!(handler.handled(), 11);
+
+        // a reorg deeper than the blocks kept re-syncs from the oldest kept
+        let (mut listener, _, handler) = build_listener(&chain, 3, 0);
+        listener.backfill().await.unwrap();
+        chain.reorg(6);
+        for id in 5..=10 {
+            chain.mine(&[id]);
+        }
+        handle(&mut listener, chain.head()).await;
+        assert_eq!(handler.rollbacks(), vec![8]);
+        // the orders of the replaced blocks before them cannot be recovered
+        let mut resynced = chain.orders(11);
+        resynced.retain(|(block, _)| *block > 8);
+        assert_eq!(handler.orders(), resynced);
+        assert_eq!(handler.handled(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_final_handlers_wait_for_confirmations() {
+        let chain = MockChain::new(4);
+        chain.mine(&[1]);
+        let (mut listener, tentative, handler) = build_listener(&chain, 8, 3);
+        listener.backfill().await.unwrap();
+        assert_eq!(tentative.orders(), chain.orders(5));
+        assert!(handler.orders().is_empty());
+        assert_eq!(handler.handled(), 2);
+
+        handle(&mut listener, chain.mine(&[2])).await;
+        handle(&mut listener, chain.mine(&[])).await;
+        assert_eq!(tentative.orders().len(), 2);
+        assert!(handler.orders().is_empty());
+        assert_eq!(handler.handled(), 4);
+
+        // a reorg within the confirmations only reaches the tentative handlers
+        chain.reorg(5);
+        chain.mine(&[3]);
+        chain.mine(&[]);
+        handle(&mut listener, chain.mine(&[])).await;
+        assert_eq!(tentative.rollbacks(), vec![4]);
+        assert_eq!(tentative.orders(), chain.orders(7));
+        assert!(handler.rollbacks().is_empty());
+        assert!(handler.orders().is_empty());
+
+        // the final handlers see each block once it is 3 blocks deep
+        for head in 8..=10 {
+            handle(&mut listener, chain.mine(&[head as u32])).await;
+            assert_eq!(handler.orders(), chain.orders(head - 3));
+            assert_eq!(handler.handled(), head - 3);
+        }
+        assert_eq!(handler.orders(), vec![(5, 3.into())]);
+        assert!(handler.rollbacks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_fetches_are_retried() {
+        let chain = MockChain::new(4);
+        chain.mine(&[1]);
+        let (mut listener, tentative, handler) = build_listener(&chain, 8, 1);
+        chain.fail_after(2);
+        listener.backfill().await.unwrap();
+        assert_eq!(tentative.orders(), chain.orders(5));
+        assert_eq!(handler.handled(), 4);
+
+        // a fetch failing at any request hands nothing over and is retried
+        chain.reorg(5);
+        chain.mine(&[2]);
+        let head = chain.mine(&[3]);
+        for requests in 0..2 {
+            chain.fail_after(requests);
+            assert!(listener.fetch_update(&head).await.is_err());
+            assert!(tentative.rollbacks().is_empty());
+        }
+        handle(&mut listener, head).await;
+        assert_eq!(tentative.rollbacks(), vec![4]);
+        assert_eq!(tentative.orders(), chain.orders(6));
+        assert_eq!(handler.orders(), chain.orders(5));
+        assert_eq!(handler.handled(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_poll_fills_gaps() {
+        let chain = MockChain::new(4);
+        let (mut listener, tentative, handler) = build_listener(&chain, 8, 1);
+        listener.backfill().await.unwrap();
+
+        // blocks mined between polls are fetched along with the head
+        chain.mine(&[1]);
+        chain.mine(&[2]);
+        let head = chain.mine(&[3]);
+        let update = listener.catch_up().await.unwrap();
+        listener.apply_update(update).await.unwrap();
+        assert_eq!(tentative.orders(), chain.orders(7));
+        assert_eq!(tentative.handled(), 7);
+        assert_eq!(handler.orders(), chain.orders(6));
+        assert_eq!(handler.handled(), 6);
+
+        // an unchanged head, or one seen again, hands nothing over
+        assert!(listener.catch_up().await.unwrap().is_none());
+        handle(&mut listener, head).await;
+        assert_eq!(tentative.orders(), chain.orders(7));
+        assert!(tentative.rollbacks().is_empty());
+
+        // blocks that failed to be fetched are polled again
+        chain.mine(&[4]);
+        chain.mine(&[5]);
+        chain.fail_after(1);
+        let polled = tokio::time::timeout(
+            Duration::from_millis(100),
+            listener.poll(Duration::from_millis(1)),
+        )
+        .await;
+        assert!(polled.is_err());
+        assert_eq!(tentative.orders(), chain.orders(9));
+        assert_eq!(handler.orders(), chain.orders(8));
+        assert_eq!(handler.handled(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_logs() {
+        let chain = MockChain::new(4);
+        let (mut listener, tentative, _) = build_listener(&chain, 8, 1);
+        listener.backfill().await.unwrap();
+        listener.streamed = Some(BTreeMap::new());
+        listener.streamed_from = 5;
+        let log_requests = chain.log_requests();
+
+        // logs streamed out of order before their header are handed over in
+        // the order they were logged, without fetching them
+        let header = chain.mine(&[1, 2]);
+        for log in chain.logs(5).into_iter().rev() {
+            stream(&mut listener, log).await;
+        }
+        handle(&mut listener, header).await;
+        assert_eq!(tentative.orders(), chain.orders(5));
+        // a block whose bloom lacks the markets' events logged none
+        handle(&mut listener, chain.mine(&[])).await;
+        assert_eq!(chain.log_requests(), log_requests);
+
+        // a header ahead of its logs has them fetched, not taken as empty
+        handle(&mut listener, chain.mine(&[3])).await;
+        assert_eq!(chain.log_requests(), log_requests + 1);
+        assert_eq!(tentative.orders(), chain.orders(7));
+        for log in chain.logs(7) {
+            stream(&mut listener, log).await;
+        }
+        assert_eq!(tentative.orders(), chain.orders(7));
+
+        // a log missing from its handled block has the block handled again
+        let header = chain.mine(&[4, 5]);
+        stream(&mut listener, chain.logs(8)[0].clone()).await;
+        handle(&mut listener, header).await;
+        assert_eq!(tentative.orders().len(), 4);
+        stream(&mut listener, chain.logs(8)[1].clone()).await;
+        assert_eq!(tentative.rollbacks(), vec![7]);
+        assert_eq!(tentative.orders(), chain.orders(8));
+        assert_eq!(tentative.handled(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_logs_without_confirmations() {
+        let chain = MockChain::new(4);
+        let (mut listener, _, handler) = build_listener(&chain, 8, 0);
+        listener.backfill().await.unwrap();
+        listener.streamed = Some(BTreeMap::new());
+        listener.streamed_from = 5;
+        let log_requests = chain.log_requests();
+
+        // the final handlers get the block at once, its logs are fetched
+        // rather than trusted to have all been streamed
+        let header = chain.mine(&[1, 2]);
+        stream(&mut listener, chain.logs(5)[0].clone()).await;
+        handle(&mut listener, header).await;
+        assert_eq!(chain.log_requests(), log_requests + 1);
+        assert_eq!(handler.orders(), chain.orders(5));
+    }
+}
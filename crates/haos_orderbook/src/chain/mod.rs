@@ -1,11 +1,58 @@
-use alloy::primitives::U256;
+use alloy::{primitives::Address, rpc::types::Log};
+use serde::{Deserialize, Serialize};
 
+use crate::orderbook::types::OrderId;
+
+pub mod backfill;
+pub mod connection;
 pub mod contract;
 pub mod listener;
 pub mod order;
+pub mod reorg;
+
+/// Position of a log on chain, events are handled in this order.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct LogPosition {
+    pub block_number: u64,
+    pub log_index: u64,
+}
 
-#[derive(Debug)]
+impl LogPosition {
+    pub fn new(block_number: u64, log_index: u64) -> Self {
+        Self {
+            block_number,
+            log_index,
+        }
+    }
+
+    pub fn of(log: &Log) -> Self {
+        Self::new(log.block_number.unwrap_or(0), log.log_index.unwrap_or(0))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ContractEvent {
-    OrderUpdated(U256, u64),
-    OrdersMatched(U256, U256, u64),
+    /// market address, order id, log position
+    OrderUpdated(Address, OrderId, LogPosition),
+    /// market address, taker order id, maker order id, log position, block timestamp
+    OrdersMatched(Address, OrderId, OrderId, LogPosition, u64),
+}
+
+impl ContractEvent {
+    pub fn market(&self) -> Address {
+        match self {
+            ContractEvent::OrderUpdated(market, ..) | ContractEvent::OrdersMatched(market, ..) => {
+                *market
+            }
+        }
+    }
+
+    pub fn position(&self) -> LogPosition {
+        match self {
+            ContractEvent::OrderUpdated(_, _, position)
+            | ContractEvent::OrdersMatched(_, _, _, position, _) => *position,
+        }
+    }
 }
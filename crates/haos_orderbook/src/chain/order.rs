@@ -1,24 +1,47 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 use alloy::{
-    primitives::{Address, TxHash, U256},
-    providers::Provider,
+    network::Ethereum,
+    primitives::{Address, TxHash},
+    providers::{PendingTransactionBuilder, Provider},
     transports::http::{Client, Http},
 };
 use anyhow::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use tracing::warn;
 
 use super::contract::IOrderBook;
 use crate::{
+    config::MarketConfig,
     constants::MATCH_GAS_LIMIT,
     orderbook::{
-        order::{Order, OrderSide},
+        order::{Order, OrderSide, Outcome, TimeInForce},
+        types::{OrderId, Price, Quantity},
         MatchedOrders,
     },
 };
 
+/// The contract holds no order with this id, e.g. one placed in blocks a
+/// reorg orphaned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrderNotFound(pub OrderId);
+
+impl fmt::Display for OrderNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Order {} does not exist", self.0)
+    }
+}
+
+impl std::error::Error for OrderNotFound {}
+
 pub trait OrderMetadataReader {
     fn get_metadata(
         &self,
-        order_id: U256,
+        market: &MarketConfig,
+        order_id: OrderId,
     ) -> impl std::future::Future<Output = Result<Order>> + Send;
 }
 
@@ -37,12 +60,12 @@ pub trait OrderMetadataReader {
 // }
 
 // impl<'a, P: Provider<Http<Client>>> OrderMetadataReader for MockedOrderMetadataReader<'a, P> {
-//     async fn get_metadata(&self, order_id: U256) -> Result<Order> {
+//     async fn get_metadata(&self, order_id: OrderId) -> Result<Order> {
 //         // pro
 //         let contract = IOrderBook::new(self.contract_address, self.provider);
-//         let order = contract.getOrder(order_id).call().await?;
+//         let order = contract.getOrder(order_id.get()).call().await?;
 //         Ok(Order::new(
-//             order_id.try_into().unwrap(),
+//             order_id,
 //             0,
 //             order._1,
 //             order._2,
@@ -60,21 +83,73 @@ struct OrderResponse {
     side: bool,
     amount: u64,
     price: u64,
+    // the contract stores neither outcome nor flags, the order-scanner does
+    // not return them
+    #[serde(default)]
+    outcome: Option<Outcome>,
+    #[serde(default)]
+    time_in_force: Option<TimeInForce>,
+    #[serde(default)]
+    post_only: Option<bool>,
+    // a null expiry block is an order without one
+    #[serde(default, deserialize_with = "present")]
+    expiry_block: Option<Option<u64>>,
+}
+
+// a field that is present, even if null
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<u64>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl OrderResponse {
+    // fields the response lacks, they are read as their defaults
+    fn missing(&self) -> Vec<&'static str> {
+        [
+            ("outcome", self.outcome.is_none()),
+            ("time in force", self.time_in_force.is_none()),
+            ("post-only", self.post_only.is_none()),
+            ("expiry block", self.expiry_block.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(field, missing)| missing.then_some(field))
+        .collect()
+    }
 }
 
-pub struct FHEOrderMetadataReader {
+pub struct FHEOrderMetadataReader<P: Provider<Http<Client>>> {
     api_url: String,
+    // reads the public part of an order, the creator, from the contract
+    provider: P,
+    // whether the defaults of missing fields were reported already
+    reported_missing: AtomicBool,
 }
 
-impl FHEOrderMetadataReader {
-    pub fn new(api_url: String) -> Self {
-        Self { api_url }
+impl<P: Provider<Http<Client>>> FHEOrderMetadataReader<P> {
+    pub fn new(api_url: String, provider: P) -> Self {
+        Self {
+            api_url,
+            provider,
+            reported_missing: AtomicBool::new(false),
+        }
     }
 }
 
-impl OrderMetadataReader for FHEOrderMetadataReader {
-    async fn get_metadata(&self, order_id: U256) -> Result<Order> {
-        let url = format!("{}/order/{}", self.api_url, order_id);
+impl<P: Provider<Http<Client>>> OrderMetadataReader for FHEOrderMetadataReader<P> {
+    async fn get_metadata(&self, market: &MarketConfig, order_id: OrderId) -> Result<Order> {
+        // the creator is stored in the clear, the zero address marks a missing order
+        let creator = IOrderBook::new(market.address, &self.provider)
+            .orders(order_id.get())
+            .call()
+            .await?
+            .creator;
+        if creator.is_zero() {
+            return Err(OrderNotFound(order_id).into());
+        }
+
+        let url = format!(
+            "{}/order/{}?contract={}",
+            self.api_url, order_id, market.address
+        );
 
         let response = reqwest::get(&url)
             .await
@@ -91,37 +166,55 @@ impl OrderMetadataReader for FHEOrderMetadataReader {
             .json()
             .await
             .map_err(|e| anyhow::anyhow!("Failed to parse order response: {}", e))?;
+        let missing = order_data.missing();
+        if !missing.is_empty() && !self.reported_missing.swap(true, Ordering::Relaxed) {
+            warn!(
+                "The order scanner does not report {}, orders are read as good-till-cancel Yes orders without flags",
+                missing.join(", ")
+            );
+        }
 
+        // the contract stores both as euint32, larger values are malformed
         Ok(Order::new(
-            order_id.try_into().unwrap(),
-            0,
-            order_data.amount.try_into().unwrap(),
-            order_data.price.try_into().unwrap(),
+            order_id,
+            market.contract_id,
+            Quantity::try_from(order_data.amount)?,
+            Price::try_from(order_data.price)?,
             if order_data.side {
                 OrderSide::Sell
             } else {
                 OrderSide::Buy
             },
-        ))
+        )
+        .with_outcome(order_data.outcome.unwrap_or_default())
+        .with_time_in_force(order_data.time_in_force.unwrap_or_default())
+        .with_post_only(order_data.post_only.unwrap_or_default())
+        .with_expiry_block(order_data.expiry_block.flatten())
+        .with_creator(Some(creator)))
     }
 }
 
-pub async fn match_orders<P: Provider<Http<Client>>>(
+/// Sends a `matchOrders` transaction without waiting for it to be mined, the
+/// wallet's nonce filler numbers transactions sent one after another in order.
+pub async fn send_match_orders<P: Provider<Http<Client>>>(
     orders: MatchedOrders,
     wallet: &P,
     contract_address: Address,
-) -> Result<TxHash> {
+) -> Result<PendingTransactionBuilder<Http<Client>, Ethereum>> {
     let contract = IOrderBook::new(contract_address, wallet);
-    let tx_receipt = contract
-        .matchOrders(
-            U256::from(orders.taker_order_id),
-            U256::from(orders.maker_order_id),
-        )
+    let pending = contract
+        .matchOrders(orders.taker_order_id.get(), orders.maker_order_id.get())
         .gas(MATCH_GAS_LIMIT)
         .send()
-        .await?
-        .get_receipt()
         .await?;
+    Ok(pending)
+}
+
+/// Waits for a `matchOrders` transaction to be mined, failing if it reverted.
+pub async fn confirm_match_orders(
+    pending: PendingTransactionBuilder<Http<Client>, Ethereum>,
+) -> Result<TxHash> {
+    let tx_receipt = pending.get_receipt().await?;
     if tx_receipt.status() {
         Ok(tx_receipt.transaction_hash)
     } else {
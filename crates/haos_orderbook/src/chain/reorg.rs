@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+
+use alloy::primitives::B256;
+
+use super::ContractEvent;
+
+/// Hashes of the latest blocks and the events they logged, to detect a
+/// reorg and undo what the orphaned blocks logged.
+#[derive(Debug)]
+pub struct BlockHistory {
+    depth: usize,
+    // block numbers and hashes, oldest first
+    blocks: VecDeque<(u64, B256)>,
+    // events of the kept blocks in the order they were logged
+    events: VecDeque<ContractEvent>,
+}
+
+impl BlockHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            blocks: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Number of blocks kept.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn latest(&self) -> Option<(u64, B256)> {
+        self.blocks.back().copied()
+    }
+
+    /// Oldest block a reorg can still be undone from.
+    pub fn oldest(&self) -> Option<u64> {
+        self.blocks.front().map(|(number, _)| *number)
+    }
+
+    pub fn hash(&self, number: u64) -> Option<B256> {
+        self.blocks
+            .iter()
+            .find(|(block, _)| *block == number)
+            .map(|(_, hash)| *hash)
+    }
+
+    /// Whether a block at `number` with `parent_hash` extends the kept chain.
+    pub fn extends(&self, number: u64, parent_hash: B256) -> bool {
+        match self.latest() {
+            Some((latest, hash)) => latest + 1 == number && hash == parent_hash,
+            None => true,
+        }
+    }
+
+    /// Adds a block on top of the kept chain, forgetting the oldest blocks
+    /// and their events past the depth.
+    pub fn push_block(&mut self, number: u64, hash: B256) {
+        self.blocks.push_back((number, hash));
+        while self.blocks.len() > self.depth {
+            self.blocks.pop_front();
+        }
+        let oldest = self.oldest().unwrap_or(number);
+        while self
+            .events
+            .front()
+            .is_some_and(|event| event.position().block_number < oldest)
+        {
+            self.events.pop_front();
+        }
+    }
+
+    /// Keeps the events of the kept blocks, in the order they were logged.
+    pub fn push_events(&mut self, events: &[ContractEvent]) {
+        let Some(oldest) = self.oldest() else {
+            return;
+        };
+        for event in events
+            .iter()
+            .filter(|event| event.position().block_number >= oldest)
+        {
+            let index = self
+                .events
+                .partition_point(|kept| kept.position() <= event.position());
+            self.events.insert(index, event.clone());
+        }
+    }
+
+    /// Events of the kept blocks from `from` to `to`, inclusive.
+    pub fn events(&self, from: u64, to: u64) -> Vec<ContractEvent> {
+        self.events
+            .iter()
+            .filter(|event| (from..=to).contains(&event.position().block_number))
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets the blocks after `number` and returns the events they logged.
+    pub fn rollback(&mut self, number: u64) -> Vec<ContractEvent> {
+        self.blocks.retain(|(block, _)| *block <= number);
+        let split = self
+            .events
+            .iter()
+            .position(|event| event.position().block_number > number)
+            .unwrap_or(self.events.len());
+        self.events.split_off(split).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, B256};
+
+    use crate::chain::{reorg::BlockHistory, ContractEvent, LogPosition};
+
+    #[test]
+    fn test_rollback_orphaned_blocks() {
+        let hash = |byte: u8| B256::repeat_byte(byte);
+        let event = |id: u32, block_number| {
+            ContractEvent::OrderUpdated(Address::ZERO, id.into(), LogPosition::new(block_number, 0))
+        };
+        let mut history = BlockHistory::new(3);
+        for number in 1..=4 {
+            history.push_block(number, hash(number as u8));
+            history.push_events(&[event(number as u32, number)]);
+        }
+        // block 1 and its event fell out of the history
+        assert_eq!(history.oldest(), Some(2));
+        assert_eq!(history.hash(1), None);
+        assert_eq!(history.events(0, 2), vec![event(2, 2)]);
+        history.push_events(&[event(5, 1)]);
+        assert_eq!(history.events(0, 4).len(), 3);
+        // an event that arrived late is kept in the order it was logged
+        history.push_events(&[event(6, 3)]);
+        assert_eq!(history.events(3, 3), vec![event(3, 3), event(6, 3)]);
+        assert!(history.extends(5, hash(4)));
+        assert!(!history.extends(5, hash(9)));
+        assert!(!history.extends(4, hash(3)));
+
+        // a new block 4 replaces the old one, which is rolled back
+        let orphaned = history.rollback(3);
+        assert_eq!(orphaned, vec![event(4, 4)]);
+        assert_eq!(history.latest(), Some((3, hash(3))));
+        assert!(history.extends(4, hash(3)));
+        history.push_block(4, hash(14));
+
+        assert_eq!(
+            history.rollback(1),
+            vec![event(2, 2), event(3, 3), event(6, 3)]
+        );
+        assert_eq!(history.latest(), None);
+    }
+}
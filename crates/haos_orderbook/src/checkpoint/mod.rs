@@ -0,0 +1,93 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alloy::primitives::Address;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Last block every handler finished with, for the markets listened to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub block_number: u64,
+    pub addresses: Vec<Address>,
+}
+
+/// Keeps the checkpoint in a single file, replaced on every save.
+#[derive(Clone, Debug)]
+pub struct CheckpointStore {
+    path: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the checkpoint under a temporary name first so that a crash
+    /// never leaves a partial one.
+    pub fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(checkpoint)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Block the checkpoint was saved at, if it was saved for the same
+    /// markets.
+    pub fn load(&self, addresses: &[Address]) -> Result<Option<u64>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let checkpoint: Checkpoint = serde_json::from_slice(&fs::read(&self.path)?)?;
+        if checkpoint.addresses != addresses {
+            warn!(
+                "Ignoring checkpoint at block {} for markets {:?}",
+                checkpoint.block_number, checkpoint.addresses
+            );
+            return Ok(None);
+        }
+        Ok(Some(checkpoint.block_number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use alloy::primitives::Address;
+
+    use crate::checkpoint::{Checkpoint, CheckpointStore};
+
+    #[test]
+    fn test_checkpoint_store() {
+        let dir = env::temp_dir().join(format!("haos-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = CheckpointStore::new(dir.join("checkpoint.json"));
+        let addresses = vec![Address::repeat_byte(1)];
+        assert_eq!(store.load(&addresses).unwrap(), None);
+
+        for block_number in [10, 11] {
+            store
+                .save(&Checkpoint {
+                    block_number,
+                    addresses: addresses.clone(),
+                })
+                .unwrap();
+        }
+        assert_eq!(store.load(&addresses).unwrap(), Some(11));
+        // a checkpoint of other markets is not resumed from
+        assert_eq!(store.load(&[Address::repeat_byte(2)]).unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
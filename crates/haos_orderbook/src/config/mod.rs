@@ -1,14 +1,118 @@
-use std::{env, str::FromStr};
+use std::{env, str::FromStr, time::Duration};
 
 use alloy::primitives::Address;
+use anyhow::{bail, Result};
+
+use crate::{
+    constants::{BACKFILL_WINDOW, REORG_DEPTH},
+    orderbook::{
+        bands::{CircuitBreaker, PriceBand},
+        execution::ContractVariant,
+        fees::FeeSchedule,
+        policy::MatchingPolicy,
+        SelfTradePrevention,
+    },
+    validation::OrderRules,
+};
+
+/// A prediction market served by this matcher, backed by its own
+/// OrderBook contract.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MarketConfig {
+    pub contract_id: u32,
+    pub address: Address,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub matching_policy: MatchingPolicy,
+    /// Clear the market in a batch auction every this many blocks instead
+    /// of matching continuously.
+    pub auction_interval: Option<u64>,
+    pub contract_variant: ContractVariant,
+    pub order_rules: OrderRules,
+    /// Fees charged on fills, the market is free if unset.
+    pub fee_schedule: Option<FeeSchedule>,
+    pub price_band: Option<PriceBand>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+impl MarketConfig {
+    pub fn new(contract_id: u32, address: Address) -> Self {
+        Self {
+            contract_id,
+            address,
+            self_trade_prevention: SelfTradePrevention::default(),
+            matching_policy: MatchingPolicy::default(),
+            auction_interval: None,
+            contract_variant: ContractVariant::default(),
+            order_rules: OrderRules::default(),
+            fee_schedule: None,
+            price_band: None,
+            circuit_breaker: None,
+        }
+    }
+
+    pub fn with_self_trade_prevention(
+        mut self,
+        self_trade_prevention: SelfTradePrevention,
+    ) -> Self {
+        self.self_trade_prevention = self_trade_prevention;
+        self
+    }
+
+    pub fn with_matching_policy(mut self, matching_policy: MatchingPolicy) -> Self {
+        self.matching_policy = matching_policy;
+        self
+    }
+
+    pub fn with_auction_interval(mut self, auction_interval: Option<u64>) -> Self {
+        self.auction_interval = auction_interval.filter(|blocks| *blocks > 0);
+        self
+    }
+
+    pub fn with_contract_variant(mut self, contract_variant: ContractVariant) -> Self {
+        self.contract_variant = contract_variant;
+        self
+    }
+
+    pub fn with_order_rules(mut self, order_rules: OrderRules) -> Self {
+        self.order_rules = order_rules;
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, fee_schedule: Option<FeeSchedule>) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    pub fn with_price_band(mut self, price_band: Option<PriceBand>) -> Self {
+        self.price_band = price_band;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, circuit_breaker: Option<CircuitBreaker>) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct ChainConfig {
     pub rpc_url: String,
     pub rpc_url_ws: String,
-    pub orderbook_address: Address,
+    pub markets: Vec<MarketConfig>,
     pub private_key: String,
     pub orderbook_start_block: u64,
+    /// Number of recent blocks a reorg can be undone in.
+    pub reorg_depth: usize,
+    /// Number of blocks an event must be buried under before it is matched on.
+    pub confirmations: u64,
+    /// Largest number of blocks logs are fetched for at once.
+    pub backfill_window: u64,
+    /// Poll `rpc_url` for new blocks at this interval instead of
+    /// subscribing over `rpc_url_ws`.
+    pub poll_interval: Option<Duration>,
+    /// Stream the markets' logs over `rpc_url_ws` instead of fetching them
+    /// for every new block.
+    pub stream_logs: bool,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -16,32 +120,192 @@ pub struct FheDecryptionConfig {
     pub api_url: String,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SnapshotConfig {
+    pub dir: String,
+    pub interval_blocks: u64,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Config {
     pub chain: ChainConfig,
     pub fhe_decryption: FheDecryptionConfig,
+    pub snapshot: Option<SnapshotConfig>,
+    /// File the order book journal is appended to, journaling is off if unset
+    pub journal_path: Option<String>,
+    /// File the last fully handled block is recorded in, the listener starts
+    /// from `orderbook_start_block` on every run if unset. Requires `snapshot`
+    pub checkpoint_path: Option<String>,
+    /// Address the admin endpoint listens on, it is not served if unset
+    pub admin_addr: Option<String>,
 }
 
-pub fn resolve_config() -> Config {
-    Config {
+pub fn resolve_config() -> Result<Config> {
+    // the books are resumed from a snapshot, a checkpoint alone would resume
+    // the listener after blocks the empty books never saw
+    if env::var("CHECKPOINT_PATH").is_ok() && env::var("SNAPSHOT_DIR").is_err() {
+        bail!("CHECKPOINT_PATH needs SNAPSHOT_DIR, the books are resumed from a snapshot");
+    }
+    Ok(Config {
         chain: ChainConfig {
             rpc_url: "https://api.nitrogen.fhenix.zone".to_string(),
             rpc_url_ws: "wss://api.nitrogen.fhenix.zone:8548".to_string(),
-            orderbook_address: Address::from_str(
+            markets: resolve_markets(
                 env::var("CONTRACT_ADDRESS")
                     .expect("CONTRACT_ADDRESS env var not set")
                     .as_str(),
-            )
-            .unwrap(),
+            )?,
             private_key: env::var("PRIVATE_KEY").expect("PRIVATE_KEY env var not set"),
             orderbook_start_block: env::var("START_BLOCK")
                 .expect("START_BLOCK env var not set")
                 .parse()
                 .expect("START_BLOCK env var not a number"),
+            reorg_depth: env_or("REORG_DEPTH", REORG_DEPTH),
+            confirmations: env_or("CONFIRMATIONS", 0),
+            backfill_window: env_or("BACKFILL_WINDOW", BACKFILL_WINDOW),
+            poll_interval: env::var("POLL_INTERVAL_MS").ok().map(|interval| {
+                Duration::from_millis(
+                    interval
+                        .parse()
+                        .expect("POLL_INTERVAL_MS env var is not valid"),
+                )
+            }),
+            stream_logs: env_or("STREAM_LOGS", false),
         },
         fhe_decryption: FheDecryptionConfig {
             api_url: env::var("FHE_DECRYPTION_API_URL")
                 .expect("FHE_DECRYPTION_API_URL env var not set"),
         },
+        snapshot: env::var("SNAPSHOT_DIR").ok().map(|dir| SnapshotConfig {
+            dir,
+            interval_blocks: env_or("SNAPSHOT_INTERVAL", 100),
+        }),
+        journal_path: env::var("JOURNAL_PATH").ok(),
+        checkpoint_path: env::var("CHECKPOINT_PATH").ok(),
+        admin_addr: env::var("ADMIN_ADDR").ok(),
+    })
+}
+
+/// Parses a comma separated list of OrderBook contract addresses.
+/// Markets are numbered in the order they are listed and share the
+/// matching settings given by the optional env vars, except for the
+/// matching policy which may also be listed per market.
+fn resolve_markets(addresses: &str) -> Result<Vec<MarketConfig>> {
+    let self_trade_prevention = env_or("SELF_TRADE_PREVENTION", SelfTradePrevention::default());
+    let contract_variant = env_or("CONTRACT_VARIANT", ContractVariant::default());
+    let defaults = OrderRules::default();
+    let order_rules = OrderRules {
+        tick_size: env_or("TICK_SIZE", defaults.tick_size),
+        lot_size: env_or("LOT_SIZE", defaults.lot_size),
+        min_price: env_or("MIN_PRICE", defaults.min_price),
+        max_price: env_or("MAX_PRICE", defaults.max_price),
+        max_volume: env::var("MAX_VOLUME")
+            .ok()
+            .map(|volume| volume.parse().expect("MAX_VOLUME env var is not valid"))
+            .or(defaults.max_volume),
+    };
+    let matching_policies = env::var("MATCHING_POLICY")
+        .map(|policies| {
+            policies
+                .split(',')
+                .map(|policy| {
+                    policy
+                        .trim()
+                        .parse::<MatchingPolicy>()
+                        .expect("MATCHING_POLICY env var is not valid")
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    // a policy whose fills the contract cannot settle would stall matching
+    if let Some(policy) = matching_policies
+        .iter()
+        .find(|policy| !contract_variant.supports(**policy))
+    {
+        bail!(
+            "MATCHING_POLICY {:?} cannot be settled by the {:?} contract, it is simulation-only",
+            policy,
+            contract_variant
+        );
+    }
+    let auction_interval = env::var("AUCTION_INTERVAL").ok().map(|blocks| {
+        blocks
+            .parse::<u64>()
+            .expect("AUCTION_INTERVAL env var is not valid")
+    });
+    // fees are only charged if one of the fee env vars is set
+    let fee_schedule = [
+        "TAKER_FEE_BPS",
+        "MAKER_FEE_BPS",
+        "MAKER_REBATE_BPS",
+        "MIN_FEE",
+        "MAX_FEE_BPS",
+    ]
+    .iter()
+    .any(|name| env::var(name).is_ok())
+    .then(|| FeeSchedule {
+        taker_bps: env_or("TAKER_FEE_BPS", 0),
+        maker_bps: env_or("MAKER_FEE_BPS", 0),
+        maker_rebate_bps: env_or("MAKER_REBATE_BPS", 0),
+        min_fee: env_or("MIN_FEE", 0),
+        max_fee_bps: env::var("MAX_FEE_BPS")
+            .ok()
+            .map(|bps| bps.parse().expect("MAX_FEE_BPS env var is not valid")),
+    });
+    let price_band = env::var("PRICE_BAND").ok().map(|width| PriceBand {
+        width: width.parse().expect("PRICE_BAND env var is not valid"),
+        reference: env::var("REFERENCE_PRICE").ok().map(|price| {
+            price
+                .parse::<u32>()
+                .expect("REFERENCE_PRICE env var is not valid")
+                .into()
+        }),
+    });
+    let circuit_breaker = env::var("BREAKER_MAX_MOVE")
+        .ok()
+        .map(|max_move| CircuitBreaker {
+            max_move: max_move
+                .parse()
+                .expect("BREAKER_MAX_MOVE env var is not valid"),
+            window_blocks: env_or("BREAKER_WINDOW", 10),
+            halt_blocks: env::var("BREAKER_HALT_BLOCKS").ok().map(|blocks| {
+                blocks
+                    .parse()
+                    .expect("BREAKER_HALT_BLOCKS env var is not valid")
+            }),
+        });
+
+    Ok(addresses
+        .split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .enumerate()
+        .map(|(contract_id, address)| {
+            MarketConfig::new(
+                contract_id as u32,
+                Address::from_str(address).expect("CONTRACT_ADDRESS is not a valid address"),
+            )
+            .with_self_trade_prevention(self_trade_prevention)
+            .with_matching_policy(match matching_policies.as_slice() {
+                [policy] => *policy,
+                policies => policies.get(contract_id).copied().unwrap_or_default(),
+            })
+            .with_auction_interval(auction_interval)
+            .with_contract_variant(contract_variant)
+            .with_order_rules(order_rules)
+            .with_fee_schedule(fee_schedule)
+            .with_price_band(price_band)
+            .with_circuit_breaker(circuit_breaker)
+        })
+        .collect())
+}
+
+/// Reads an optional env var, falling back to `default` when it is not set.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} env var is not valid", name)),
+        Err(_) => default,
     }
 }
@@ -1 +1,32 @@
+use std::time::Duration;
+
 pub const MATCH_GAS_LIMIT: u64 = 8_000_000;
+
+/// Price of a complete set of one Yes and one No token.
+pub const UNIT_PRICE: u32 = 100;
+
+/// Largest volume of an order by default, `FHERC20.mint` refuses to mint
+/// more than this at once.
+pub const MAX_ORDER_VOLUME: u32 = 100;
+
+/// Number of trades kept per market for price discovery.
+pub const MAX_TRADE_HISTORY: usize = 100_000;
+
+/// Number of recent blocks kept to detect and undo reorgs.
+pub const REORG_DEPTH: usize = 64;
+
+/// Largest number of blocks logs are fetched for at once.
+pub const BACKFILL_WINDOW: u64 = 10_000;
+
+/// Chunks of logs smaller than this grow the backfill window again.
+pub const BACKFILL_SMALL_RESULT: usize = 1_000;
+
+/// Delay before the first attempt to restore the block subscription.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between attempts to restore the block subscription.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Time without a block header after which the subscription is restored
+/// as stalled.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(60);
@@ -0,0 +1,301 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chain::LogPosition,
+    orderbook::{
+        lifecycle::MarketState,
+        order::Order,
+        types::{OrderId, Price},
+        Fill, MatchedOrders, OrderBook,
+    },
+};
+
+/// An input that changed, or was checked against, an order book.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// The book moved to a new block, which expires good-till-block orders.
+    BlockAdvanced { block_number: u64 },
+    /// An order was added or replaced with its decrypted metadata.
+    OrderUpserted { order: Order },
+    /// An order left the book without being filled.
+    OrderRemoved { id: OrderId },
+    /// A fill was sent for settlement and applied to the local book.
+    MatchProposed { fill: Fill },
+    /// A match was confirmed by an `OrdersMatched` event.
+    MatchConfirmed { orders: MatchedOrders },
+    /// The market moved to another lifecycle state.
+    StateChanged { state: MarketState },
+    /// A reorg orphaned the blocks after `block_number`, the entries that
+    /// follow undo what they logged.
+    RolledBack { block_number: u64 },
+    /// A trade or a reorg moved the reference price of the price band.
+    LastPriceChanged { price: Option<Price> },
+    /// The book started over at `block_number` with these orders, restored
+    /// from a snapshot or empty to be rebuilt. Earlier entries no longer apply.
+    Restored {
+        block_number: u64,
+        orders: Vec<Order>,
+        last_price: Option<Price>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub sequence: u64,
+    pub contract_id: u32,
+    /// Position of the chain event that led to the entry.
+    pub position: LogPosition,
+    pub entry: JournalEntry,
+}
+
+/// Append-only journal of order book inputs, stored as JSON lines.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    next_sequence: u64,
+}
+
+impl Journal {
+    /// Opens a journal for appending, continuing the sequence of the
+    /// records already in it.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let next_sequence = if path.exists() {
+            Self::read(&path)?
+                .last()
+                .map_or(0, |record| record.sequence + 1)
+        } else {
+            0
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            next_sequence,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(
+        &mut self,
+        contract_id: u32,
+        position: LogPosition,
+        entry: JournalEntry,
+    ) -> Result<()> {
+        let record = JournalRecord {
+            sequence: self.next_sequence,
+            contract_id,
+            position,
+            entry,
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        self.next_sequence += 1;
+        Ok(())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<JournalRecord>> {
+        let mut records = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.is_empty() {
+                records.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Rebuilds the book of one market by applying its journal records to
+/// `book`, which should be configured like the market's book was when the
+/// journal started. A restore starts over from that configuration.
+pub fn replay<'a>(
+    records: impl IntoIterator<Item = &'a JournalRecord>,
+    contract_id: u32,
+    mut book: OrderBook,
+) -> OrderBook {
+    let configured = book.clone();
+    for record in records
+        .into_iter()
+        .filter(|record| record.contract_id == contract_id)
+    {
+        match &record.entry {
+            JournalEntry::BlockAdvanced { block_number } => book.set_block_number(*block_number),
+            JournalEntry::OrderUpserted { order } => {
+                book.update_order(order.clone());
+            }
+            JournalEntry::OrderRemoved { id } => book.cancel_orders(&[*id]),
+            JournalEntry::MatchProposed { fill } => book.apply_fill(fill),
+            JournalEntry::LastPriceChanged { price } => book.set_last_price(*price),
+            JournalEntry::Restored {
+                block_number,
+                orders,
+                last_price,
+            } => {
+                book = configured.clone();
+                book.set_block_number(*block_number);
+                for order in orders {
+                    book.add_order(order.clone());
+                }
+                book.set_last_price(*last_price);
+            }
+            JournalEntry::MatchConfirmed { .. }
+            | JournalEntry::StateChanged { .. }
+            | JournalEntry::RolledBack { .. } => {}
+        }
+    }
+    book
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::{
+        chain::LogPosition,
+        journal::{replay, Journal, JournalEntry},
+        orderbook::{
+            order::{Order, OrderSide, TimeInForce},
+            OrderBook,
+        },
+    };
+
+    #[test]
+    fn test_replay_rebuilds_book() {
+        let path = env::temp_dir().join(format!("haos-journal-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut journal = Journal::open(&path).unwrap();
+        let position = LogPosition::new(2, 0);
+
+        // change the book the way the manager does and journal each input
+        let mut book = OrderBook::new();
+        book.set_block_number(2);
+        journal
+            .append(0, position, JournalEntry::BlockAdvanced { block_number: 2 })
+            .unwrap();
+        for order in [
+            Order::new(1, 0, 10, 40, OrderSide::Sell),
+            Order::new(2, 0, 4, 45, OrderSide::Buy),
+            Order::new(3, 0, 9, 41, OrderSide::Buy)
+                .with_time_in_force(TimeInForce::ImmediateOrCancel),
+            Order::new(4, 0, 5, 50, OrderSide::Sell),
+        ] {
+            book.update_order(order.clone());
+            journal
+                .append(0, position, JournalEntry::OrderUpserted { order })
+                .unwrap();
+        }
+        let result = book.run_matching();
+        book.cancel_orders(&result.cancelled);
+        for id in result.cancelled {
+            journal
+                .append(0, position, JournalEntry::OrderRemoved { id })
+                .unwrap();
+        }
+        for fill in result.fills {
+            book.apply_fill(&fill);
+            journal
+                .append(0, position, JournalEntry::MatchProposed { fill })
+                .unwrap();
+        }
+
+        // a confirmed trade moves the band's reference
+        book.set_last_price(Some(45.into()));
+        journal
+            .append(
+                0,
+                position,
+                JournalEntry::LastPriceChanged {
+                    price: Some(45.into()),
+                },
+            )
+            .unwrap();
+
+        // reopening continues the sequence, other markets are not replayed
+        drop(journal);
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .append(
+                1,
+                LogPosition::new(3, 0),
+                JournalEntry::OrderRemoved { id: 1.into() },
+            )
+            .unwrap();
+
+        let records = Journal::read(&path).unwrap();
+        assert_eq!(records.last().unwrap().sequence, records.len() as u64 - 1);
+
+        let replayed = replay(records.iter(), 0, OrderBook::new());
+        assert_eq!(replayed, book);
+        // the unfilled rest of the immediate-or-cancel order was cancelled
+        assert_eq!(replayed.len(), 1);
+        assert!(replayed.get_order(4.into()).is_some());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_across_restore() {
+        let path =
+            env::temp_dir().join(format!("haos-journal-restore-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut journal = Journal::open(&path).unwrap();
+        let position = LogPosition::new(2, 0);
+        let upsert = |journal: &mut Journal, order: Order| {
+            journal
+                .append(0, position, JournalEntry::OrderUpserted { order })
+                .unwrap();
+        };
+        let restored = |journal: &mut Journal, book: &OrderBook| {
+            let entry = JournalEntry::Restored {
+                block_number: book.block_number(),
+                orders: book.orders().cloned().collect(),
+                last_price: book.last_price(),
+            };
+            journal.append(0, position, entry).unwrap();
+        };
+
+        // the first run journals two orders
+        let mut book = OrderBook::new();
+        restored(&mut journal, &book);
+        upsert(&mut journal, Order::new(1, 0, 10, 40, OrderSide::Sell));
+        upsert(&mut journal, Order::new(2, 0, 4, 30, OrderSide::Buy));
+
+        // a restart from a snapshot holding one of them
+        book.set_block_number(5);
+        book.add_order(Order::new(1, 0, 10, 40, OrderSide::Sell));
+        book.set_last_price(Some(42.into()));
+        restored(&mut journal, &book);
+        let order = Order::new(3, 0, 2, 35, OrderSide::Buy);
+        book.update_order(order.clone());
+        upsert(&mut journal, order);
+        let records = Journal::read(&path).unwrap();
+        assert_eq!(replay(records.iter(), 0, OrderBook::new()), book);
+
+        // a restart without a snapshot rebuilds the book from the start
+        let mut book = OrderBook::new();
+        restored(&mut journal, &book);
+        let order = Order::new(1, 0, 10, 40, OrderSide::Sell);
+        book.update_order(order.clone());
+        upsert(&mut journal, order);
+        let records = Journal::read(&path).unwrap();
+        let replayed = replay(records.iter(), 0, OrderBook::new());
+        assert_eq!(replayed, book);
+        assert_eq!(replayed.len(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,54 +1,105 @@
-use alloy::primitives::U256;
+use alloy::primitives::Address;
 use anyhow::Result;
-use chain::order::OrderMetadataReader;
-use orderbook::MatchedOrders;
+use chain::{order::OrderMetadataReader, ContractEvent, LogPosition};
+use config::MarketConfig;
+use orderbook::{types::OrderId, MatchedOrders};
 use tracing::info;
 
+pub mod admin;
 pub mod chain;
+pub mod checkpoint;
 pub mod config;
 pub mod constants;
+pub mod journal;
 pub mod manager;
 pub mod orderbook;
+pub mod prices;
+pub mod snapshot;
+pub mod validation;
 
 /// Handler trait for processing orders
 pub trait OrderHandler: Send + Sync {
+    /// Receives updated orders as (market address, order id, log position)
     fn handle_orders(
         &mut self,
-        orders: Vec<(U256, u64)>,
+        orders: Vec<(Address, OrderId, LogPosition)>,
     ) -> impl std::future::Future<Output = Result<()>> + Send;
+    /// Receives orders matched on chain by the log at `position`, in a block
+    /// with `timestamp`
     fn match_orders(
         &mut self,
+        market: Address,
         orders: MatchedOrders,
+        position: LogPosition,
+        timestamp: u64,
     ) -> impl std::future::Future<Output = Result<()>> + Send;
+    /// Called when a reorg orphaned the blocks after `block_number`, with the
+    /// events they logged, before the canonical blocks are handled
+    fn rollback(
+        &mut self,
+        _block_number: u64,
+        _orphaned: Vec<ContractEvent>,
+    ) -> impl std::future::Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+    /// Called once every event up to and including `block_number` was handled
+    fn blocks_handled(
+        &mut self,
+        _block_number: u64,
+    ) -> impl std::future::Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
 }
 
 pub struct LoggingOrderHandler<T: OrderMetadataReader> {
     order_metadata_reader: T,
+    markets: Vec<MarketConfig>,
 }
 
 impl<T: OrderMetadataReader> LoggingOrderHandler<T> {
-    pub fn new(order_metadata_reader: T) -> Self {
+    pub fn new(order_metadata_reader: T, markets: Vec<MarketConfig>) -> Self {
         Self {
             order_metadata_reader,
+            markets,
         }
     }
-    async fn handle_order(&mut self, id: U256, block_number: u64) -> Result<()> {
-        info!("Order {} at block {}", id, block_number);
-        let metadata = self.order_metadata_reader.get_metadata(id).await?;
+    async fn handle_order(
+        &mut self,
+        market: Address,
+        id: OrderId,
+        position: LogPosition,
+    ) -> Result<()> {
+        info!(
+            "Order {} of market {} at block {}",
+            id, market, position.block_number
+        );
+        let Some(market) = self.markets.iter().find(|m| m.address == market) else {
+            return Ok(());
+        };
+        let metadata = self.order_metadata_reader.get_metadata(market, id).await?;
         info!("Order metadata: {:?}", metadata);
         Ok(())
     }
 }
 
 impl<T: OrderMetadataReader + Send + Sync> OrderHandler for LoggingOrderHandler<T> {
-    async fn handle_orders(&mut self, orders: Vec<(U256, u64)>) -> Result<()> {
-        for (id, block_number) in orders {
-            self.handle_order(id, block_number).await?;
+    async fn handle_orders(&mut self, orders: Vec<(Address, OrderId, LogPosition)>) -> Result<()> {
+        for (market, id, position) in orders {
+            self.handle_order(market, id, position).await?;
         }
         Ok(())
     }
-    async fn match_orders(&mut self, orders: MatchedOrders) -> Result<()> {
-        info!("Matched orders: {:?}", orders);
+    async fn match_orders(
+        &mut self,
+        market: Address,
+        orders: MatchedOrders,
+        position: LogPosition,
+        _timestamp: u64,
+    ) -> Result<()> {
+        info!(
+            "Matched orders in market {} at block {}: {:?}",
+            market, position.block_number, orders
+        );
         Ok(())
     }
 }
@@ -4,32 +4,42 @@ use alloy::{
     network::EthereumWallet,
     providers::{
         fillers::{NonceFiller, SimpleNonceManager},
-        ProviderBuilder, WsConnect,
+        Provider, ProviderBuilder, WsConnect,
     },
     signers::local::PrivateKeySigner,
+    transports::Transport,
 };
 use anyhow::Result;
 use haos_orderbook::{
-    chain::{listener::OrderListener, order::FHEOrderMetadataReader},
-    config::resolve_config,
+    admin,
+    chain::{
+        listener::{OrderListener, OrderListenerBuilder},
+        order::FHEOrderMetadataReader,
+    },
+    checkpoint::CheckpointStore,
+    config::{resolve_config, ChainConfig},
+    constants::STALL_TIMEOUT,
+    journal::Journal,
     manager::OrderManager,
+    snapshot::SnapshotStore,
+    OrderHandler,
 };
+use tokio::sync::mpsc;
+use tracing::{error, warn};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = resolve_config();
+    let config = resolve_config()?;
     init_tracing();
 
-    let ws_provider = ProviderBuilder::new()
-        .on_ws(WsConnect::new(config.chain.rpc_url_ws))
-        .await?;
-
-    let mocked_order_metadata_reader: FHEOrderMetadataReader =
-        FHEOrderMetadataReader::new(config.fhe_decryption.api_url.clone());
+    let mocked_order_metadata_reader = FHEOrderMetadataReader::new(
+        config.fhe_decryption.api_url.clone(),
+        ProviderBuilder::new().on_http(config.chain.rpc_url.parse()?),
+    );
 
     let wallet = EthereumWallet::from(PrivateKeySigner::from_str(
-        &config.chain.private_key.as_str(),
+        config.chain.private_key.as_str(),
     )?);
 
     let wallet_provider = ProviderBuilder::new()
@@ -38,19 +48,116 @@ async fn main() -> Result<()> {
         .wallet(wallet)
         .on_http(config.chain.rpc_url.parse()?);
 
-    let mut listener = OrderListener::builder(&ws_provider)
-        .with_start_block(config.chain.orderbook_start_block)
-        .with_address(config.chain.orderbook_address)
-        .with_handler(OrderManager::new(
-            mocked_order_metadata_reader,
-            wallet_provider,
-            config.chain.orderbook_address,
-        ))
-        .build()?;
+    let mut manager = OrderManager::new(
+        mocked_order_metadata_reader,
+        wallet_provider,
+        config.chain.markets.clone(),
+    );
+    if let Some(path) = config.journal_path.as_ref() {
+        manager = manager.with_journal(Journal::open(path)?);
+    }
+    // operators change market states through the admin endpoint, the
+    // manager applies them as it handles blocks
+    if let Some(addr) = config.admin_addr.clone() {
+        let (commands, received) = mpsc::channel(16);
+        manager = manager.with_admin_commands(received);
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(&addr, commands).await {
+                error!("Admin endpoint on {} failed: {:?}", addr, e);
+            }
+        });
+    }
+
+    // the last block every handler finished, snapshots after it may hold
+    // blocks a reorg orphaned
+    let addresses = config
+        .chain
+        .markets
+        .iter()
+        .map(|market| market.address)
+        .collect::<Vec<_>>();
+    let checkpoints = config.checkpoint_path.as_ref().map(CheckpointStore::new);
+    let checkpoint = match &checkpoints {
+        Some(store) => store.load(&addresses)?,
+        None => None,
+    };
+
+    // the books are only as recent as their newest snapshot, backfill the
+    // blocks after it. Without one they are rebuilt from START_BLOCK
+    let mut start_block = config.chain.orderbook_start_block;
+    let mut restored = false;
+    if let Some(snapshot_config) = config.snapshot.as_ref() {
+        let store = SnapshotStore::new(&snapshot_config.dir);
+        if let Some(snapshot) = store.load_latest_until(checkpoint.unwrap_or(u64::MAX))? {
+            match manager.restore(snapshot) {
+                Ok(block_number) => {
+                    start_block = block_number + 1;
+                    restored = true;
+                }
+                Err(e) => warn!("Ignoring snapshot: {}", e),
+            }
+        }
+        manager = manager.with_snapshots(store, snapshot_config.interval_blocks);
+    }
+    if let (Some(block_number), false) = (checkpoint, restored) {
+        warn!(
+            "No snapshot at or before checkpoint block {}, rebuilding the books from block {}",
+            block_number, start_block
+        );
+    }
+    // the journal replays from the books this run starts with
+    manager.journal_books()?;
+
+    match config.chain.poll_interval {
+        // HTTP endpoints cannot push new blocks, they are polled
+        Some(interval) => {
+            let provider = ProviderBuilder::new().on_http(config.chain.rpc_url.parse()?);
+            let listener = OrderListener::builder(&provider).with_polling(interval);
+            listen(listener, &config.chain, start_block, checkpoints, manager).await
+        }
+        None => {
+            let ws_provider = ProviderBuilder::new()
+                .on_ws(WsConnect::new(config.chain.rpc_url_ws.clone()))
+                .await?;
+            let rpc_url_ws = config.chain.rpc_url_ws.clone();
+            let mut listener =
+                OrderListener::builder(&ws_provider).with_connector(Box::new(move || {
+                    let connect = WsConnect::new(rpc_url_ws.clone());
+                    Box::pin(async move { Ok(ProviderBuilder::new().on_ws(connect).await?) })
+                }));
+            if config.chain.stream_logs {
+                listener = listener.with_log_streaming();
+            }
+            listen(listener, &config.chain, start_block, checkpoints, manager).await
+        }
+    }
+}
 
-    listener.listen().await?;
+// listen with the settings shared by every block source
+async fn listen<P: Provider<T>, T: Transport + Clone, H: OrderHandler>(
+    listener: OrderListenerBuilder<'_, P, T, H>,
+    config: &ChainConfig,
+    start_block: u64,
+    checkpoints: Option<CheckpointStore>,
+    handler: H,
+) -> Result<()> {
+    let mut listener = listener
+        .with_start_block(start_block)
+        .with_reorg_depth(config.reorg_depth)
+        .with_confirmations(config.confirmations)
+        .with_backfill_window(config.backfill_window)
+        // a quiet market still receives block headers, a stalled stream does not
+        .with_stall_timeout(STALL_TIMEOUT)
+        .with_handler(handler);
+    for market in config.markets.iter() {
+        listener = listener.with_address(market.address);
+    }
+    if let Some(store) = checkpoints {
+        listener = listener.with_checkpoints(store);
+    }
+    let mut listener = listener.build()?;
 
-    Ok(())
+    listener.listen().await
 }
 
 fn init_tracing() {
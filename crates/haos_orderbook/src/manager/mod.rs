@@ -1,87 +1,834 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
 use alloy::{
-    primitives::{Address, U256},
+    primitives::Address,
     providers::Provider,
     transports::http::{Client, Http},
 };
 use anyhow::Result;
-use tracing::{error, info};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
 use crate::{
-    chain::order::{match_orders, OrderMetadataReader},
-    orderbook::{MatchedOrders, OrderBook},
+    admin::AdminCommand,
+    chain::{
+        order::{confirm_match_orders, send_match_orders, OrderMetadataReader, OrderNotFound},
+        ContractEvent, LogPosition,
+    },
+    config::MarketConfig,
+    journal::{Journal, JournalEntry},
+    orderbook::{
+        lifecycle::MarketState,
+        markets::MarketBooks,
+        order::{Order, Outcome},
+        types::{OrderId, OutOfRange},
+        Fill, MatchKind, MatchedOrders, MatchingResult,
+    },
+    prices::Trade,
+    snapshot::{Snapshot, SnapshotStore, SNAPSHOT_VERSION},
+    validation::{Quarantine, QuarantinedOrder, Rejection},
     OrderHandler,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct OrderManager<T: OrderMetadataReader, P: Provider<Http<Client>>> {
     order_metadata_reader: T,
     wallet: P,
-    contract_address: Address,
-    orderbook: OrderBook,
-    waiting_orders: Vec<(U256, u64)>,
-    pending_matched_orders: Option<MatchedOrders>,
+    orderbooks: MarketBooks,
+    waiting_orders: Vec<(Address, OrderId, LogPosition)>,
+    // settlements awaiting confirmation per market, keyed by contract id
+    pending_matched_orders: HashMap<u32, VecDeque<Fill>>,
+    // where to write snapshots and how many blocks apart
+    snapshots: Option<(SnapshotStore, u64)>,
+    last_snapshot_block: u64,
+    // records every input applied to the books
+    journal: Option<Journal>,
+    // position of the latest event handled, recorded with journal entries
+    position: LogPosition,
+    // decrypted orders that failed validation
+    quarantine: Quarantine,
+    // resolved markets whose resting orders were reported for unwinding
+    unwinding_reported: HashSet<u32>,
+    // circuit breaker halts and resumes with the state each replaced, to
+    // undo them on a reorg
+    state_changes: Vec<(LogPosition, u32, MarketState)>,
+    // requests of operators, applied before the next events are handled
+    admin_commands: Option<mpsc::Receiver<AdminCommand>>,
 }
 
 impl<T: OrderMetadataReader, P: Provider<Http<Client>>> OrderManager<T, P> {
-    pub fn new(order_metadata_reader: T, wallet: P, contract_address: Address) -> Self {
+    pub fn new(order_metadata_reader: T, wallet: P, markets: Vec<MarketConfig>) -> Self {
         Self {
             order_metadata_reader,
-            orderbook: OrderBook::new(),
+            orderbooks: MarketBooks::new(markets),
             waiting_orders: Vec::new(),
-            pending_matched_orders: None,
+            pending_matched_orders: HashMap::new(),
             wallet,
-            contract_address,
+            snapshots: None,
+            last_snapshot_block: 0,
+            journal: None,
+            position: LogPosition::default(),
+            quarantine: Quarantine::default(),
+            unwinding_reported: HashSet::new(),
+            state_changes: Vec::new(),
+            admin_commands: None,
         }
     }
-    async fn add_orders(&mut self, orders: &[(U256, u64)]) -> Result<()> {
+
+    /// Writes a snapshot at most every `interval` blocks.
+    pub fn with_snapshots(mut self, store: SnapshotStore, interval: u64) -> Self {
+        self.snapshots = Some((store, interval));
+        self
+    }
+
+    /// Journals every change to the books so that they can be replayed.
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Applies the commands of the admin endpoint as blocks are handled.
+    pub fn with_admin_commands(mut self, commands: mpsc::Receiver<AdminCommand>) -> Self {
+        self.admin_commands = Some(commands);
+        self
+    }
+
+    pub fn snapshot(&self, block_number: u64) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            block_number,
+            markets: self
+                .orderbooks
+                .iter()
+                .map(|market| market.snapshot())
+                .collect(),
+            waiting_orders: self.waiting_orders.clone(),
+            pending_fills: self
+                .pending_matched_orders
+                .iter()
+                .flat_map(|(contract_id, fills)| {
+                    fills.iter().map(|fill| (*contract_id, fill.clone()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Resumes from a snapshot and returns its block. Fails without changing
+    /// any state if the snapshot was taken for a different set of markets.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<u64> {
+        let markets = snapshot
+            .markets
+            .iter()
+            .map(|market| (market.contract_id, market.address))
+            .collect::<Vec<_>>();
+        let configured = self
+            .orderbooks
+            .iter()
+            .map(|market| (market.config.contract_id, market.config.address))
+            .collect::<Vec<_>>();
+        if markets != configured {
+            return Err(anyhow::anyhow!(
+                "Snapshot markets {:?} do not match configured markets {:?}",
+                markets,
+                configured
+            ));
+        }
+
+        for market in snapshot.markets {
+            if let Some(book) = self.orderbooks.market_mut(market.contract_id) {
+                book.restore(market);
+            }
+        }
+        self.waiting_orders = snapshot.waiting_orders;
+        self.pending_matched_orders.clear();
+        for (contract_id, fill) in snapshot.pending_fills {
+            self.pending_matched_orders
+                .entry(contract_id)
+                .or_default()
+                .push_back(fill);
+        }
+        self.last_snapshot_block = snapshot.block_number;
+        info!("Restored snapshot at block {}", snapshot.block_number);
+        Ok(snapshot.block_number)
+    }
+
+    /// Journals the books as they are as the state later entries apply to,
+    /// once they were restored or before they are rebuilt.
+    pub fn journal_books(&mut self) -> Result<()> {
+        let entries = self
+            .orderbooks
+            .iter()
+            .map(|market| {
+                (
+                    market.config.contract_id,
+                    JournalEntry::Restored {
+                        block_number: market.book.block_number(),
+                        orders: market.book.orders().cloned().collect(),
+                        last_price: market.book.last_price(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        for (contract_id, entry) in entries {
+            self.journal(contract_id, entry)?;
+        }
+        Ok(())
+    }
+
+    pub fn orderbooks(&self) -> &MarketBooks {
+        &self.orderbooks
+    }
+
+    pub fn quarantine(&self) -> &Quarantine {
+        &self.quarantine
+    }
+
+    /// Moves a market to another lifecycle state, on a chain event or an
+    /// admin's request.
+    pub fn set_market_state(&mut self, contract_id: u32, state: MarketState) -> Result<()> {
+        let Some(market) = self.orderbooks.market_mut(contract_id) else {
+            return Err(anyhow::anyhow!("Unknown market {}", contract_id));
+        };
+        market.set_state(state)?;
+        info!("Market {} is {}", contract_id, state);
+        self.journal(contract_id, JournalEntry::StateChanged { state })?;
+        self.report_unwinding();
+        Ok(())
+    }
+
+    // apply the admin commands received since the last block and reply
+    // with their outcome
+    fn apply_admin_commands(&mut self) {
+        let mut commands = Vec::new();
+        if let Some(receiver) = self.admin_commands.as_mut() {
+            while let Ok(command) = receiver.try_recv() {
+                commands.push(command);
+            }
+        }
+        for command in commands {
+            match command {
+                AdminCommand::SetMarketState {
+                    contract_id,
+                    state,
+                    reply,
+                } => {
+                    let result = self.set_market_state(contract_id, state);
+                    if let Err(e) = &result {
+                        warn!("Admin could not move market {}: {}", contract_id, e);
+                    }
+                    let _ = reply.send(result);
+                }
+            }
+        }
+    }
+
+    /// Resting orders of a resolved market, once its pending settlements
+    /// are drained.
+    pub fn orders_to_unwind(&self, contract_id: u32) -> Vec<&Order> {
+        match self.orderbooks.market(contract_id) {
+            Some(market)
+                if matches!(market.state(), MarketState::Resolved(_))
+                    && !self.pending_matched_orders.contains_key(&contract_id) =>
+            {
+                market.book.orders().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // report the resting orders of resolved markets once, after their
+    // pending settlements were confirmed
+    fn report_unwinding(&mut self) {
+        for market in self.orderbooks.iter() {
+            let contract_id = market.config.contract_id;
+            if !matches!(market.state(), MarketState::Resolved(_))
+                || self.pending_matched_orders.contains_key(&contract_id)
+                || !self.unwinding_reported.insert(contract_id)
+            {
+                continue;
+            }
+            warn!(
+                "Market {} is {}, {} resting orders to unwind: {:?}",
+                contract_id,
+                market.state(),
+                market.book.len(),
+                market
+                    .book
+                    .orders()
+                    .map(|order| order.id)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    fn journal(&mut self, contract_id: u32, entry: JournalEntry) -> Result<()> {
+        match &mut self.journal {
+            Some(journal) => journal.append(contract_id, self.position, entry),
+            None => Ok(()),
+        }
+    }
+
+    // keep an order out of the book, an earlier version that was valid leaves it
+    fn quarantine_order(
+        &mut self,
+        contract_id: u32,
+        id: OrderId,
+        order: Option<Order>,
+        rejections: Vec<Rejection>,
+        position: LogPosition,
+    ) -> Result<()> {
+        warn!(
+            "Quarantined order {} of market {}: {}",
+            id,
+            contract_id,
+            rejections
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if let Some(market) = self.orderbooks.market_mut(contract_id) {
+            if market.book.remove_order(id) {
+                if let Some(journal) = &mut self.journal {
+                    journal.append(contract_id, position, JournalEntry::OrderRemoved { id })?;
+                }
+            }
+        }
+        self.quarantine.insert(QuarantinedOrder {
+            contract_id,
+            id,
+            order,
+            rejections,
+            position,
+        });
+        Ok(())
+    }
+
+    // drop an order the contract does not hold from the book and the quarantine
+    fn remove_missing_order(
+        &mut self,
+        contract_id: u32,
+        id: OrderId,
+        position: LogPosition,
+    ) -> Result<()> {
+        warn!(
+            "Order {} of market {} does not exist, removing it",
+            id, contract_id
+        );
+        self.quarantine.release(contract_id, id);
+        if let Some(market) = self.orderbooks.market_mut(contract_id) {
+            if market.book.remove_order(id) {
+                if let Some(journal) = &mut self.journal {
+                    journal.append(contract_id, position, JournalEntry::OrderRemoved { id })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_block_number(&mut self, block_number: u64) -> Result<()> {
+        self.orderbooks.set_block_number(block_number);
+        let contract_ids = self
+            .orderbooks
+            .iter()
+            .map(|market| market.config.contract_id)
+            .collect::<Vec<_>>();
+        for contract_id in contract_ids {
+            self.journal(contract_id, JournalEntry::BlockAdvanced { block_number })?;
+        }
+        Ok(())
+    }
+
+    async fn add_orders(&mut self, orders: &[(Address, OrderId, LogPosition)]) -> Result<()> {
         // find unique order ids, as they may be duplicated
         let mut orders = orders.to_vec();
-        orders.sort_by(|a, b| a.0.cmp(&b.0));
-        orders.dedup_by(|a, b| a.0 == b.0);
+        orders.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        orders.dedup_by(|a, b| (a.0, a.1) == (b.0, b.1));
+
+        for (market, id, position) in orders.iter() {
+            let Some(market) = self.orderbooks.market_by_address(*market) else {
+                warn!("Order {} for unknown market {}", id, market);
+                continue;
+            };
+            let order = match self
+                .order_metadata_reader
+                .get_metadata(&market.config, *id)
+                .await
+            {
+                Ok(order) => order,
+                Err(e) => {
+                    let contract_id = market.config.contract_id;
+                    // an order the contract could not have stored is quarantined, not retried
+                    if let Some(out_of_range) = e.downcast_ref::<OutOfRange>() {
+                        let rejections = vec![Rejection::from(out_of_range)];
+                        self.quarantine_order(contract_id, *id, None, rejections, *position)?;
+                        continue;
+                    }
+                    // an order that only existed in orphaned blocks is gone
+                    if e.downcast_ref::<OrderNotFound>().is_some() {
+                        self.remove_missing_order(contract_id, *id, *position)?;
+                        continue;
+                    }
+                    return Err(e);
+                }
+            };
+            let contract_id = order.contract_id;
+
+            // a market that is closing or resolved only tracks the orders it has
+            if !market.state().accepts_new_orders()
+                && !order.volume.is_zero()
+                && market.book.get_order(order.id).is_none()
+            {
+                warn!(
+                    "Market {} is {}, ignoring new order {}",
+                    contract_id,
+                    market.state(),
+                    order.id
+                );
+                continue;
+            }
+
+            // the contract only settles Yes fills, a No order rests unmatched
+            if !market
+                .config
+                .contract_variant
+                .settles(MatchKind::Direct, order.outcome)
+                && !order.volume.is_zero()
+                && market.book.get_order(order.id).is_none()
+            {
+                warn!(
+                    "Order {} of market {} is a {:?} order the {:?} contract cannot settle, it is never matched",
+                    order.id, contract_id, order.outcome, market.config.contract_variant
+                );
+            }
+
+            let rejections = market.config.order_rules.check(&order);
+            if !rejections.is_empty() {
+                self.quarantine_order(contract_id, order.id, Some(order), rejections, *position)?;
+                continue;
+            }
+            if self.quarantine.release(contract_id, order.id).is_some() {
+                info!(
+                    "Order {} of market {} released from quarantine",
+                    order.id, contract_id
+                );
+            }
+            if self.orderbooks.update_order(order.clone()) {
+                if let Some(journal) = &mut self.journal {
+                    journal.append(
+                        contract_id,
+                        *position,
+                        JournalEntry::OrderUpserted { order },
+                    )?;
+                }
+            }
+        }
+        for market in self.orderbooks.iter() {
+            info!(
+                "Orderbook {}: {} orders, Yes {:?}, No {:?}",
+                market.config.contract_id,
+                market.book.len(),
+                market.book.top_of_book(Outcome::Yes),
+                market.book.top_of_book(Outcome::No),
+            );
+        }
+        Ok(())
+    }
 
-        for (id, _) in orders.iter() {
-            let order = self.order_metadata_reader.get_metadata(*id).await?;
-            self.orderbook.update_order(order);
+    // settle fills in order, later fills depend on the earlier ones so stop at the first failure.
+    // The fills are all sent before any of them is waited for
+    async fn settle_orders(&mut self, contract_id: u32, fills: Vec<Fill>) -> Result<()> {
+        let mut sent = Vec::new();
+        for fill in fills {
+            let Some(market) = self.orderbooks.market_mut(contract_id) else {
+                return Ok(());
+            };
+            // settle what the contract will execute, which may differ from the
+            // proposed fill, e.g. when an earlier fill was not confirmed yet
+            let Some(execution) = market.book.execute(&fill.matched_orders()) else {
+                warn!(
+                    "Contract would revert match for market {}: {:?}",
+                    contract_id, fill
+                );
+                break;
+            };
+            let orders = fill.matched_orders().with_execution(&execution);
+            let proposed = fill;
+            let fill = market.book.with_fees(Fill {
+                price: execution.price.unwrap_or(proposed.price),
+                volume: execution.volume,
+                ..proposed.clone()
+            });
+            if fill != proposed {
+                warn!(
+                    "Contract executes {:?} instead of proposed {:?}",
+                    fill, proposed
+                );
+            }
+            // the band was checked at the proposed price, hold fills that
+            // execute outside it
+            if let Some((low, high)) = market
+                .book
+                .price_band(fill.outcome)
+                .filter(|(low, high)| fill.price < *low || fill.price > *high)
+            {
+                warn!(
+                    "Held back match of market {} executing at {} outside the price band {}..={}: {:?}",
+                    contract_id, fill.price, low, high, fill
+                );
+                break;
+            }
+            info!(
+                "Settling orders on chain for market {}: {:?}",
+                contract_id, orders
+            );
+
+            let pending = match send_match_orders(
+                orders.clone(),
+                &self.wallet,
+                market.config.address,
+            )
+            .await
+            {
+                Ok(pending) => pending,
+                Err(e) => {
+                    error!("Failed to settle orders: {:?}", e);
+                    break;
+                }
+            };
+            // track the partial fill locally until the chain confirms it
+            market.book.apply_fill(&fill);
+            self.journal(
+                contract_id,
+                JournalEntry::MatchProposed { fill: fill.clone() },
+            )?;
+            self.pending_matched_orders
+                .entry(contract_id)
+                .or_default()
+                .push_back(fill.clone());
+            sent.push((fill, pending));
+        }
+
+        for (fill, pending) in sent {
+            if let Err(e) = confirm_match_orders(pending).await {
+                error!("Failed to settle orders: {:?}", e);
+                self.drop_pending_fill(contract_id, &fill);
+            }
         }
-        info!("Orderbook: {:?}", self.orderbook);
         Ok(())
     }
 
-    async fn settle_orders(&mut self, orders: MatchedOrders) -> Result<()> {
-        info!("Settling orders on chain: {:?}", orders);
+    // a settlement that failed is never confirmed, its orders are read again
+    // instead of waiting for it
+    fn drop_pending_fill(&mut self, contract_id: u32, fill: &Fill) {
+        let Some(pending) = self.pending_matched_orders.get_mut(&contract_id) else {
+            return;
+        };
+        let orders = fill.matched_orders();
+        if let Some(position) = pending
+            .iter()
+            .position(|pending| pending.matched_orders().is_same_pair(&orders))
+        {
+            pending.remove(position);
+        }
+        if pending.is_empty() {
+            self.pending_matched_orders.remove(&contract_id);
+        }
+        let Some(market) = self.orderbooks.market(contract_id) else {
+            return;
+        };
+        let address = market.config.address;
+        self.waiting_orders.extend(
+            [orders.taker_order_id, orders.maker_order_id].map(|id| (address, id, self.position)),
+        );
+    }
 
-        let result = match_orders(orders.clone(), &self.wallet, self.contract_address).await;
-        if result.is_err() {
-            error!("Failed to settle orders: {:?}", result);
-        } else {
-            self.pending_matched_orders = Some(orders);
+    // cancel what a matching pass or auction reported and settle its fills
+    async fn settle_results(&mut self, results: Vec<(u32, MatchingResult)>) -> Result<()> {
+        for (contract_id, result) in results {
+            if self.pending_matched_orders.contains_key(&contract_id) {
+                continue;
+            }
+            if let Some(market) = self.orderbooks.market_mut(contract_id) {
+                market.book.cancel_orders(&result.cancelled);
+            }
+            for id in result.cancelled.iter().copied() {
+                self.journal(contract_id, JournalEntry::OrderRemoved { id })?;
+            }
+            if !result.held.is_empty() {
+                warn!(
+                    "Held back matches outside the price band of market {}: {:?}",
+                    contract_id, result.held
+                );
+            }
+            if result.fills.is_empty() {
+                continue;
+            }
+            info!(
+                "Matched orders, {} in fees: {:?}",
+                result.revenue(),
+                result.fills
+            );
+            self.settle_orders(contract_id, result.fills).await?;
         }
         Ok(())
     }
+
+    fn is_pending(&self, market: Address) -> bool {
+        self.orderbooks
+            .market_by_address(market)
+            .is_some_and(|market| {
+                self.pending_matched_orders
+                    .contains_key(&market.config.contract_id)
+            })
+    }
 }
 
 impl<T: OrderMetadataReader + Send + Sync, P: Provider<Http<Client>>> OrderHandler
     for OrderManager<T, P>
 {
-    async fn handle_orders(&mut self, orders: Vec<(U256, u64)>) -> Result<()> {
+    async fn handle_orders(&mut self, orders: Vec<(Address, OrderId, LogPosition)>) -> Result<()> {
+        self.apply_admin_commands();
+        if let Some(position) = orders.iter().map(|(_, _, position)| *position).max() {
+            self.position = self.position.max(position);
+            self.set_block_number(position.block_number)?;
+        }
         self.waiting_orders.extend(orders);
-        if self.pending_matched_orders.is_some() {
+
+        // orders of a market with a pending settlement wait for its confirmation
+        let (waiting, ready): (Vec<_>, Vec<_>) = self
+            .waiting_orders
+            .iter()
+            .partition(|(market, _, _)| self.is_pending(*market));
+        self.add_orders(&ready).await?;
+        self.waiting_orders = waiting;
+        self.report_unwinding();
+
+        let results = self.orderbooks.run_matching();
+        self.settle_results(results).await
+    }
+
+    async fn blocks_handled(&mut self, block_number: u64) -> Result<()> {
+        self.apply_admin_commands();
+        let resumed: Vec<u32> = self
+            .orderbooks
+            .iter_mut()
+            .filter_map(|market| {
+                market
+                    .resume_after_halt(block_number)
+                    .then_some(market.config.contract_id)
+            })
+            .collect();
+        for contract_id in resumed {
+            self.state_changes.push((
+                LogPosition::new(block_number, 0),
+                contract_id,
+                MarketState::Halted,
+            ));
+            info!(
+                "Resumed market {} at block {} after its halt",
+                contract_id, block_number
+            );
+            self.journal(
+                contract_id,
+                JournalEntry::StateChanged {
+                    state: MarketState::Open,
+                },
+            )?;
+        }
+
+        // markets with a pending settlement hold their auction once it is confirmed
+        if self.orderbooks.iter().any(|market| {
+            market.is_auction_due(block_number)
+                && !self
+                    .pending_matched_orders
+                    .contains_key(&market.config.contract_id)
+        }) {
+            self.position = self.position.max(LogPosition::new(block_number, 0));
+            self.set_block_number(block_number)?;
+            let pending = &self.pending_matched_orders;
+            let results = self.orderbooks.run_auctions(block_number, |contract_id| {
+                !pending.contains_key(&contract_id)
+            });
+            self.settle_results(results).await?;
+        }
+
+        let Some((store, interval)) = &self.snapshots else {
+            return Ok(());
+        };
+        if block_number < self.last_snapshot_block + interval {
             return Ok(());
         }
-        self.add_orders(&self.waiting_orders.clone()).await?;
-        self.waiting_orders.clear();
+        // a failed snapshot only costs a longer replay, keep matching
+        match store.save(&self.snapshot(block_number)) {
+            Ok(_) => self.last_snapshot_block = block_number,
+            Err(e) => error!("Failed to save snapshot: {:?}", e),
+        }
+        Ok(())
+    }
+
+    async fn rollback(&mut self, block_number: u64, orphaned: Vec<ContractEvent>) -> Result<()> {
+        warn!(
+            "Rolling back {} events after block {}",
+            orphaned.len(),
+            block_number
+        );
+        self.position = self.position.min(LogPosition::new(block_number + 1, 0));
+        let contract_ids = self
+            .orderbooks
+            .iter()
+            .map(|market| market.config.contract_id)
+            .collect::<Vec<_>>();
+        for contract_id in contract_ids {
+            self.journal(contract_id, JournalEntry::RolledBack { block_number })?;
+        }
+
+        // undo the state changes of the orphaned blocks, latest first
+        while self
+            .state_changes
+            .last()
+            .is_some_and(|(position, _, _)| position.block_number > block_number)
+        {
+            let Some((_, contract_id, state)) = self.state_changes.pop() else {
+                break;
+            };
+            if let Some(market) = self.orderbooks.market_mut(contract_id) {
+                market.revert_state(state);
+                info!("Market {} is {} again", contract_id, state);
+            }
+            self.unwinding_reported.remove(&contract_id);
+            self.journal(contract_id, JournalEntry::StateChanged { state })?;
+        }
+        let mut last_prices = Vec::new();
+        for market in self.orderbooks.iter_mut() {
+            let last_price = market.book.last_price();
+            market.rollback_trades(block_number);
+            if market.book.last_price() != last_price {
+                last_prices.push((market.config.contract_id, market.book.last_price()));
+            }
+        }
+        for (contract_id, price) in last_prices {
+            self.journal(contract_id, JournalEntry::LastPriceChanged { price })?;
+        }
+        self.last_snapshot_block = self.last_snapshot_block.min(block_number);
 
-        if let Some(matched_orders) = self.orderbook.find_matching_orders() {
-            info!("Matched orders: {:?}", matched_orders);
-            self.settle_orders(matched_orders).await?;
+        // settlements of the markets the reorg touched may never be
+        // confirmed, their orders are read again instead of waiting
+        let affected = orphaned
+            .iter()
+            .filter_map(|event| self.orderbooks.market_by_address(event.market()))
+            .map(|market| (market.config.contract_id, market.config.address))
+            .collect::<HashSet<_>>();
+        let mut dropped = Vec::new();
+        for (contract_id, market) in affected {
+            let Some(fills) = self.pending_matched_orders.remove(&contract_id) else {
+                continue;
+            };
+            warn!(
+                "Dropping {} pending settlements of market {} after the reorg",
+                fills.len(),
+                contract_id
+            );
+            for fill in fills {
+                let orders = fill.matched_orders();
+                dropped.push((market, orders.taker_order_id));
+                dropped.push((market, orders.maker_order_id));
+            }
         }
+
+        // orders touched by the orphaned events are read again with the
+        // canonical blocks, which undoes what the orphaned events changed
+        let position = self.position;
+        self.waiting_orders
+            .retain(|(_, _, position)| position.block_number <= block_number);
+        self.waiting_orders.extend(
+            orphaned
+                .iter()
+                .flat_map(|event| match *event {
+                    ContractEvent::OrderUpdated(market, id, _) => vec![(market, id)],
+                    ContractEvent::OrdersMatched(market, taker_id, maker_id, ..) => {
+                        vec![(market, taker_id), (market, maker_id)]
+                    }
+                })
+                .chain(dropped)
+                .map(|(market, id)| (market, id, position)),
+        );
         Ok(())
     }
 
-    async fn match_orders(&mut self, orders: MatchedOrders) -> Result<()> {
-        if self.pending_matched_orders.is_some() {
-            self.pending_matched_orders = None;
-            info!("Confirmed orders matching: {:?}", orders);
+    async fn match_orders(
+        &mut self,
+        market: Address,
+        orders: MatchedOrders,
+        position: LogPosition,
+        timestamp: u64,
+    ) -> Result<()> {
+        let Some(contract_id) = self
+            .orderbooks
+            .market_by_address(market)
+            .map(|market| market.config.contract_id)
+        else {
+            return Ok(());
+        };
+
+        self.position = self.position.max(position);
+        self.journal(
+            contract_id,
+            JournalEntry::MatchConfirmed {
+                orders: orders.clone(),
+            },
+        )?;
+
+        let mut confirmed = None;
+        if let Some(pending) = self.pending_matched_orders.get_mut(&contract_id) {
+            if let Some(position) = pending
+                .iter()
+                .position(|pending| pending.matched_orders().is_same_pair(&orders))
+            {
+                confirmed = pending.remove(position);
+                info!(
+                    "Confirmed orders matching for market {}: {:?}",
+                    contract_id, orders
+                );
+            }
+            if pending.is_empty() {
+                self.pending_matched_orders.remove(&contract_id);
+            }
+        }
+
+        let Some(market) = self.orderbooks.market_mut(contract_id) else {
+            return Ok(());
+        };
+        // a match settled by someone else is priced from the orders in the book
+        let Some(fill) = confirmed.or_else(|| market.book.fill_for(&orders)) else {
+            return Ok(());
+        };
+        let last_price = market.book.last_price();
+        market.record_trade(Trade {
+            block_number: position.block_number,
+            timestamp,
+            outcome: fill.outcome,
+            price: fill.price.get(),
+            volume: fill.volume.get(),
+        });
+        let price = market.book.last_price();
+        let tripped = market.check_circuit_breaker(position.block_number);
+        if price != last_price {
+            self.journal(contract_id, JournalEntry::LastPriceChanged { price })?;
+        }
+        if tripped {
+            self.state_changes
+                .push((position, contract_id, MarketState::Open));
+            warn!(
+                "Halted market {} at block {}, its price moved too far",
+                contract_id, position.block_number
+            );
+            self.journal(
+                contract_id,
+                JournalEntry::StateChanged {
+                    state: MarketState::Halted,
+                },
+            )?;
         }
         Ok(())
     }
@@ -0,0 +1,168 @@
+use super::{
+    level::BookSide,
+    order::{OrderSide, Outcome},
+    types::Price,
+    MatchKind, MatchState, MatchingResult, OrderBook,
+};
+
+impl OrderBook {
+    /// Clears each outcome in a single auction at a uniform price, instead
+    /// of matching orders as they arrive.
+    ///
+    /// The clearing price is the one that matches the most volume. Ties go
+    /// to the price that leaves the least volume unmatched on either side,
+    /// then to the middle of the remaining prices. Bids at or above and asks
+    /// at or below it are then matched in priority order, as a matching pass
+    /// would. Only direct fills are made, and a book settled on chain only
+    /// auctions what its contract can settle.
+    ///
+    /// The clearing price is advisory: `matchOrders` executes each pair at
+    /// the lower of its two prices, so fills are priced as the contract
+    /// settles them. Only where the contract moves no tokens are they priced
+    /// at the clearing price.
+    pub fn run_auction(&self) -> MatchingResult {
+        let mut state = self.match_state();
+        let mut fills = Vec::new();
+
+        for outcome in [Outcome::Yes, Outcome::No] {
+            if !self.settles(MatchKind::Direct, outcome) {
+                continue;
+            }
+            let bids = self.side(outcome, OrderSide::Buy);
+            let asks = self.side(outcome, OrderSide::Sell);
+            let Some(price) = clearing_price(&state, bids, asks) else {
+                continue;
+            };
+            state.clearing_price = Some(price);
+            fills.extend(state.cross(MatchKind::Direct, bids, asks, |buy, sell| {
+                buy.price >= price && sell.price <= price
+            }));
+        }
+        self.matching_result(state, fills)
+    }
+}
+
+/// Price that maximises the matched volume of two crossed book sides.
+fn clearing_price(state: &MatchState, bids: &BookSide, asks: &BookSide) -> Option<Price> {
+    let best_bid = bids.best()?.price;
+    let best_ask = asks.best()?.price;
+    if best_bid < best_ask {
+        return None;
+    }
+
+    // active volume per price level within the crossed range
+    let volumes = |side: &BookSide, crossed: &dyn Fn(Price) -> bool| {
+        side.levels()
+            .take_while(|(price, _)| crossed(*price))
+            .map(|(price, level)| {
+                let volume = level
+                    .iter()
+                    .filter(|order| state.is_active(order))
+                    .map(|order| u64::from(state.remaining(order)))
+                    .sum::<u64>();
+                (price, volume)
+            })
+            .collect::<Vec<_>>()
+    };
+    let bids = volumes(bids, &|price| price >= best_ask);
+    let asks = volumes(asks, &|price| price <= best_bid);
+
+    let mut prices = bids
+        .iter()
+        .chain(asks.iter())
+        .map(|(price, _)| *price)
+        .collect::<Vec<_>>();
+    prices.sort_unstable();
+    prices.dedup();
+
+    // (matched volume, imbalance) at each candidate price
+    let outcomes = prices
+        .iter()
+        .map(|price| {
+            let demand = bids
+                .iter()
+                .filter(|(bid, _)| bid >= price)
+                .map(|(_, volume)| volume)
+                .sum::<u64>();
+            let supply = asks
+                .iter()
+                .filter(|(ask, _)| ask <= price)
+                .map(|(_, volume)| volume)
+                .sum::<u64>();
+            (demand.min(supply), demand.abs_diff(supply))
+        })
+        .collect::<Vec<_>>();
+    let best = outcomes
+        .iter()
+        .filter(|(volume, _)| *volume > 0)
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))?;
+    let ties = prices
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| *outcome == best)
+        .map(|(price, _)| *price)
+        .collect::<Vec<_>>();
+    Some(ties[(ties.len() - 1) / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::orderbook::{
+        execution::ContractVariant,
+        order::{Order, OrderSide, Outcome},
+        types::{OrderId, Price, Quantity},
+        Fill, OrderBook,
+    };
+
+    #[test]
+    fn test_auction_clears_at_uniform_price() {
+        let mut book = OrderBook::new();
+        book.add_order(Order::new(1, 1, 10, 45, OrderSide::Buy));
+        book.add_order(Order::new(2, 1, 10, 42, OrderSide::Buy));
+        book.add_order(Order::new(3, 1, 10, 38, OrderSide::Buy));
+        book.add_order(Order::new(4, 1, 5, 40, OrderSide::Sell));
+        book.add_order(Order::new(5, 1, 10, 41, OrderSide::Sell).with_post_only(true));
+        book.add_order(Order::new(6, 1, 10, 44, OrderSide::Sell));
+        book.add_order(Order::new(7, 1, 5, 60, OrderSide::Buy).with_outcome(Outcome::No));
+
+        // 41 and 42 both match 15 and leave 5 unmatched, 41 is the lower middle
+        let result = book.run_auction();
+        let prices = |fills: &[Fill]| {
+            fills
+                .iter()
+                .map(|fill| {
+                    (
+                        fill.taker_order_id,
+                        fill.maker_order_id,
+                        fill.price,
+                        fill.volume,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let fill = |taker: u32, maker: u32, price: u32, volume: u32| {
+            (
+                OrderId::from(taker),
+                OrderId::from(maker),
+                Price::from(price),
+                Quantity::from(volume),
+            )
+        };
+        // each pair settles at the lower of its prices, not the clearing price
+        assert_eq!(
+            prices(&result.fills),
+            vec![fill(4, 1, 40, 5), fill(5, 1, 41, 5), fill(5, 2, 41, 5)]
+        );
+        // the post-only order was not refused
+        assert!(result.cancelled.is_empty());
+
+        // where the contract moves no tokens the clearing price is used
+        let book = book.with_contract_variant(ContractVariant::MockedOrderBook);
+        assert_eq!(
+            prices(&book.run_auction().fills),
+            vec![fill(4, 1, 41, 5), fill(5, 1, 41, 5), fill(5, 2, 41, 5)]
+        );
+
+        assert!(OrderBook::new().run_auction().fills.is_empty());
+    }
+}
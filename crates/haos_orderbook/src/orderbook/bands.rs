@@ -0,0 +1,133 @@
+use super::{order::Outcome, types::Price};
+use crate::prices::{PriceDiscovery, Since};
+
+/// How far from a reference price a market may trade, in units of the price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceBand {
+    pub width: u32,
+    /// Fixed reference Yes price, the one implied by the last trade is used
+    /// if unset. No prices are banded around the unit price minus it.
+    pub reference: Option<Price>,
+}
+
+impl PriceBand {
+    /// Lowest and highest price allowed around `reference`.
+    pub fn range(&self, reference: Price) -> (Price, Price) {
+        let reference = reference.get();
+        (
+            reference.saturating_sub(self.width).into(),
+            reference.saturating_add(self.width).into(),
+        )
+    }
+}
+
+/// Halts a market whose price moves more than `max_move` within
+/// `window_blocks` blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub max_move: u32,
+    pub window_blocks: u64,
+    /// Blocks after which the market resumes, it stays halted until it is
+    /// resumed by hand if unset.
+    pub halt_blocks: Option<u64>,
+}
+
+impl CircuitBreaker {
+    /// Whether the trades of either outcome in the window ending at
+    /// `block_number` moved its price too far.
+    pub fn is_tripped(&self, prices: &PriceDiscovery, block_number: u64) -> bool {
+        let since = block_number.saturating_sub(self.window_blocks.saturating_sub(1));
+        [Outcome::Yes, Outcome::No].into_iter().any(|outcome| {
+            prices
+                .summary(outcome, Since::Block(since))
+                .is_some_and(|candle| candle.high - candle.low > self.max_move)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        orderbook::{
+            bands::{CircuitBreaker, PriceBand},
+            order::{Order, OrderSide, Outcome},
+            types::{OrderId, Price},
+            OrderBook,
+        },
+        prices::{PriceDiscovery, Trade},
+    };
+
+    #[test]
+    fn test_price_band_and_circuit_breaker() {
+        let band = PriceBand {
+            width: 10,
+            reference: None,
+        };
+        let mut book = OrderBook::new().with_price_band(Some(band));
+        book.add_order(Order::new(1, 0, 10, 85, OrderSide::Sell));
+        book.add_order(Order::new(2, 0, 10, 2, OrderSide::Sell));
+        book.add_order(Order::new(3, 0, 20, 95, OrderSide::Buy));
+
+        // without a trade to compare to, the whole book is swept
+        assert_eq!(book.run_matching().fills.len(), 2);
+
+        // the sell at 2 is 88 below the last trade, so it is held back
+        book.set_last_price(Some(90.into()));
+        let result = book.run_matching();
+        assert_eq!(result.fills.len(), 1);
+        assert_eq!(result.fills[0].maker_order_id, OrderId::from(1));
+        assert_eq!(result.held.len(), 1);
+        assert_eq!(result.held[0].price, Price::from(2));
+        assert!(book.find_matching_orders().is_some());
+
+        // No prices are banded around the complement of the Yes price
+        assert_eq!(book.price_band(Outcome::No), Some((0.into(), 20.into())));
+        let mut no_book = book.clone();
+        no_book.add_order(Order::new(4, 0, 5, 18, OrderSide::Buy).with_outcome(Outcome::No));
+        no_book.add_order(Order::new(5, 0, 5, 15, OrderSide::Sell).with_outcome(Outcome::No));
+        let result = no_book.run_matching();
+        assert!(result
+            .fills
+            .iter()
+            .any(|fill| fill.outcome == Outcome::No && fill.price == Price::from(15)));
+        assert_eq!(result.held.len(), 1);
+
+        // a fixed reference price is used over the last trade
+        let book = book.with_price_band(Some(PriceBand {
+            reference: Some(5.into()),
+            ..band
+        }));
+        let result = book.run_matching();
+        assert_eq!(result.fills[0].price, Price::from(2));
+        assert_eq!(result.held[0].price, Price::from(85));
+
+        let breaker = CircuitBreaker {
+            max_move: 20,
+            window_blocks: 5,
+            halt_blocks: Some(10),
+        };
+        let mut prices = PriceDiscovery::new(100);
+        let trade = |block_number, price| Trade {
+            block_number,
+            timestamp: 0,
+            outcome: Outcome::Yes,
+            price,
+            volume: 1,
+        };
+        prices.record(trade(1, 90));
+        prices.record(trade(4, 75));
+        assert!(!breaker.is_tripped(&prices, 5));
+        prices.record(trade(6, 60));
+        // 90 left the window, 75 to 60 is within the limit
+        assert!(!breaker.is_tripped(&prices, 6));
+        // the No price is a series of its own, its trades do not count as a
+        // move of the Yes price
+        prices.record(Trade {
+            outcome: Outcome::No,
+            ..trade(7, 40)
+        });
+        assert!(!breaker.is_tripped(&prices, 7));
+        prices.record(trade(7, 40));
+        assert!(breaker.is_tripped(&prices, 7));
+    }
+}
@@ -0,0 +1,122 @@
+use serde::Serialize;
+
+use super::{
+    level::BookSide,
+    order::{OrderSide, Outcome},
+    types::Price,
+    OrderBook,
+};
+
+/// Aggregated resting volume at one price.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub volume: u64,
+    pub orders: usize,
+}
+
+/// Aggregated depth (L2) of one outcome, best prices first.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Depth {
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+/// Best bid and ask (L1) of one outcome.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TopOfBook {
+    pub best_bid: Option<DepthLevel>,
+    pub best_ask: Option<DepthLevel>,
+    /// Best ask minus best bid, negative while the book is crossed.
+    pub spread: Option<i64>,
+    pub mid: Option<f64>,
+}
+
+impl OrderBook {
+    /// Returns up to `levels` price levels per side for an outcome.
+    pub fn depth(&self, outcome: Outcome, levels: usize) -> Depth {
+        Depth {
+            bids: depth_levels(self.side(outcome, OrderSide::Buy), levels),
+            asks: depth_levels(self.side(outcome, OrderSide::Sell), levels),
+        }
+    }
+
+    pub fn top_of_book(&self, outcome: Outcome) -> TopOfBook {
+        let best_bid = depth_levels(self.side(outcome, OrderSide::Buy), 1).pop();
+        let best_ask = depth_levels(self.side(outcome, OrderSide::Sell), 1).pop();
+        let (spread, mid) = match (&best_bid, &best_ask) {
+            (Some(bid), Some(ask)) => (
+                Some(i64::from(ask.price.get()) - i64::from(bid.price.get())),
+                Some((f64::from(bid.price.get()) + f64::from(ask.price.get())) / 2.0),
+            ),
+            _ => (None, None),
+        };
+        TopOfBook {
+            best_bid,
+            best_ask,
+            spread,
+            mid,
+        }
+    }
+}
+
+fn depth_levels(side: &BookSide, levels: usize) -> Vec<DepthLevel> {
+    side.levels()
+        .take(levels)
+        .map(|(price, level)| DepthLevel {
+            price,
+            volume: level.volume(),
+            orders: level.len(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::orderbook::{
+        depth::DepthLevel,
+        order::{Order, OrderSide, Outcome},
+        types::Price,
+        OrderBook,
+    };
+
+    #[test]
+    fn test_depth_and_top_of_book() {
+        let mut book = OrderBook::new();
+
+        book.add_order(Order::new(1, 1, 10, 40, OrderSide::Buy));
+        book.add_order(Order::new(2, 1, 5, 40, OrderSide::Buy));
+        book.add_order(Order::new(3, 1, 7, 38, OrderSide::Buy));
+        book.add_order(Order::new(4, 1, 3, 35, OrderSide::Buy));
+        book.add_order(Order::new(5, 1, 8, 45, OrderSide::Sell));
+        book.add_order(Order::new(6, 1, 8, 60, OrderSide::Sell).with_outcome(Outcome::No));
+
+        let depth = book.depth(Outcome::Yes, 2);
+        assert_eq!(
+            depth.bids,
+            vec![
+                DepthLevel {
+                    price: 40.into(),
+                    volume: 15,
+                    orders: 2,
+                },
+                DepthLevel {
+                    price: 38.into(),
+                    volume: 7,
+                    orders: 1,
+                },
+            ]
+        );
+        assert_eq!(depth.asks.len(), 1);
+
+        let top = book.top_of_book(Outcome::Yes);
+        assert_eq!(top.best_bid.unwrap().volume, 15);
+        assert_eq!(top.best_ask.unwrap().price, Price::from(45));
+        assert_eq!(top.spread, Some(5));
+        assert_eq!(top.mid, Some(42.5));
+
+        let top = book.top_of_book(Outcome::No);
+        assert!(top.best_bid.is_none());
+        assert_eq!(top.spread, None);
+    }
+}
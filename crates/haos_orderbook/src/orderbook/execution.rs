@@ -0,0 +1,151 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    order::{Order, OrderSide, Outcome},
+    policy::MatchingPolicy,
+    types::{Price, Quantity},
+    MatchKind,
+};
+
+/// OrderBook contract a market settles on. The variants agree on when a
+/// pair can be matched but not on what the match moves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractVariant {
+    /// `OrderBook.sol`, trades the encrypted tokens at the lower of the two prices.
+    #[default]
+    OrderBook,
+    /// `MockedOrderBook.sol`, only updates the order amounts.
+    MockedOrderBook,
+}
+
+impl FromStr for ContractVariant {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "orderbook" => Ok(ContractVariant::OrderBook),
+            "mocked" => Ok(ContractVariant::MockedOrderBook),
+            _ => Err(anyhow::anyhow!("Unknown contract variant: {}", s)),
+        }
+    }
+}
+
+/// What `matchOrders(taker, maker)` does on chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Execution {
+    pub volume: Quantity,
+    /// Price the tokens are exchanged at, `None` if no tokens are moved.
+    pub price: Option<Price>,
+    pub taker_remaining: Quantity,
+    pub maker_remaining: Quantity,
+}
+
+impl ContractVariant {
+    /// Predicts `matchOrders` for the orders as they are stored on chain.
+    /// Returns `None` where the contract reverts.
+    pub fn execute(&self, taker: &Order, maker: &Order) -> Option<Execution> {
+        let sides_different = taker.side != maker.side;
+        let taker_price_equal = taker.price == maker.price;
+        let taker_price_higher = taker.price > maker.price;
+        // a buying taker must bid more than the maker asks, a selling taker
+        // must ask less than the maker bids
+        let can_be_filled =
+            taker_price_equal || ((taker.side == OrderSide::Sell) != taker_price_higher);
+        if !sides_different || maker.volume.is_zero() || taker.volume.is_zero() || !can_be_filled {
+            return None;
+        }
+
+        let volume = taker.volume.min(maker.volume);
+        Some(Execution {
+            volume,
+            price: self.price(taker, maker),
+            taker_remaining: taker.volume - volume,
+            maker_remaining: maker.volume - volume,
+        })
+    }
+
+    /// Whether a fill of `kind` between orders of `outcome` can be settled.
+    /// Both contracts only trade `tokenA`, the Yes token, so No fills and
+    /// complete sets cannot be settled on them.
+    pub fn settles(&self, kind: MatchKind, outcome: Outcome) -> bool {
+        kind == MatchKind::Direct && outcome == Outcome::Yes
+    }
+
+    /// Whether the fills of `policy` can be settled. `matchOrders` always
+    /// fills the smaller of the two orders completely, so pro-rata shares of
+    /// an order cannot be.
+    pub fn supports(&self, policy: MatchingPolicy) -> bool {
+        policy != MatchingPolicy::ProRata
+    }
+
+    /// Price a fill of the two orders is executed at, whichever is the taker.
+    pub fn price(&self, first: &Order, second: &Order) -> Option<Price> {
+        match self {
+            ContractVariant::OrderBook => Some(first.price.min(second.price)),
+            ContractVariant::MockedOrderBook => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::orderbook::{
+        execution::{ContractVariant, Execution},
+        order::{Order, OrderSide},
+        policy::MatchingPolicy,
+    };
+
+    #[test]
+    fn test_execute_like_match_orders() {
+        let buy = Order::new(1, 0, 10, 45, OrderSide::Buy);
+        let sell = Order::new(2, 0, 4, 40, OrderSide::Sell);
+
+        // the lower price is used whichever order takes
+        let execution = Execution {
+            volume: 4.into(),
+            price: Some(40.into()),
+            taker_remaining: 0.into(),
+            maker_remaining: 6.into(),
+        };
+        assert_eq!(
+            ContractVariant::OrderBook.execute(&sell, &buy),
+            Some(execution.clone())
+        );
+        assert_eq!(
+            ContractVariant::OrderBook
+                .execute(&buy, &sell)
+                .map(|execution| execution.price),
+            Some(Some(40.into()))
+        );
+        assert_eq!(
+            ContractVariant::MockedOrderBook.execute(&sell, &buy),
+            Some(Execution {
+                price: None,
+                ..execution
+            })
+        );
+
+        // prices that do not cross, equal sides and filled orders revert
+        let high_sell = Order::new(3, 0, 4, 50, OrderSide::Sell);
+        assert_eq!(ContractVariant::OrderBook.execute(&high_sell, &buy), None);
+        assert_eq!(ContractVariant::OrderBook.execute(&buy, &high_sell), None);
+        let other_buy = Order::new(4, 0, 4, 40, OrderSide::Buy);
+        assert_eq!(ContractVariant::OrderBook.execute(&other_buy, &buy), None);
+        let filled = Order::new(5, 0, 0, 40, OrderSide::Sell);
+        assert_eq!(ContractVariant::OrderBook.execute(&filled, &buy), None);
+        let equal = Order::new(6, 0, 4, 45, OrderSide::Sell);
+        assert!(ContractVariant::MockedOrderBook
+            .execute(&equal, &buy)
+            .is_some());
+
+        // whole orders are filled, pro-rata shares are not
+        for variant in [ContractVariant::OrderBook, ContractVariant::MockedOrderBook] {
+            assert!(variant.supports(MatchingPolicy::Fifo));
+            assert!(variant.supports(MatchingPolicy::SizePriority));
+            assert!(!variant.supports(MatchingPolicy::ProRata));
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    order::{Order, OrderSide},
+    Fill, MatchKind,
+};
+
+const BPS: u64 = 10_000;
+
+/// Fees a market charges on fills, in basis points of the fill value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub maker_bps: u32,
+    pub taker_bps: u32,
+    /// Smallest fee charged to the taker of a fill.
+    pub min_fee: u64,
+    /// Paid back to the maker, on top of its own fee.
+    pub maker_rebate_bps: u32,
+    /// Fills where either order pays more than this in fees are skipped.
+    pub max_fee_bps: Option<u32>,
+}
+
+/// What one order of a fill pays and receives, in units of the price.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeLine {
+    /// Value of the order's side of the fill before fees.
+    pub gross: u64,
+    /// Negative when a rebate is paid.
+    pub fee: i64,
+    /// What a buy pays or a sell receives after fees.
+    pub net: i64,
+}
+
+/// Fees of a single fill.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FillFees {
+    pub taker: FeeLine,
+    pub maker: FeeLine,
+}
+
+impl FillFees {
+    /// What the matcher keeps from the fill.
+    pub fn revenue(&self) -> i64 {
+        self.taker.fee + self.maker.fee
+    }
+}
+
+impl FeeSchedule {
+    /// Fees of `fill` between its taker and maker order. For mint and burn
+    /// fills the taker's side is valued at the complementary price. None if
+    /// an amount does not fit a fee line.
+    pub fn fees(
+        &self,
+        fill: &Fill,
+        taker: &Order,
+        maker: &Order,
+        unit_price: u32,
+    ) -> Option<FillFees> {
+        let volume = u64::from(fill.volume);
+        let price = u64::from(fill.price);
+        let taker_price = match fill.kind {
+            MatchKind::Direct => price,
+            MatchKind::Mint | MatchKind::Burn => u64::from(unit_price).saturating_sub(price),
+        };
+
+        let taker_gross = volume * taker_price;
+        let taker_fee =
+            bps_ceil(taker_gross, self.taker_bps)?.max(i64::try_from(self.min_fee).ok()?);
+        let maker_gross = volume * price;
+        let maker_fee =
+            bps_ceil(maker_gross, self.maker_bps)? - bps_floor(maker_gross, self.maker_rebate_bps)?;
+        Some(FillFees {
+            taker: FeeLine::new(taker, taker_gross, taker_fee)?,
+            maker: FeeLine::new(maker, maker_gross, maker_fee)?,
+        })
+    }
+
+    /// Whether the fees leave the fill worth making.
+    pub fn is_economic(&self, fees: &FillFees) -> bool {
+        let Some(max_fee_bps) = self.max_fee_bps else {
+            return true;
+        };
+        [fees.taker, fees.maker]
+            .iter()
+            .all(|line| bps_floor(line.gross, max_fee_bps).map_or(true, |max| line.fee <= max))
+    }
+}
+
+impl FeeLine {
+    fn new(order: &Order, gross: u64, fee: i64) -> Option<Self> {
+        let value = i64::try_from(gross).ok()?;
+        let net = match order.side {
+            OrderSide::Buy => value.checked_add(fee)?,
+            OrderSide::Sell => value.checked_sub(fee)?,
+        };
+        Some(FeeLine { gross, fee, net })
+    }
+}
+
+// fees are rounded up, rebates down, None if they do not fit a fee line
+fn bps_ceil(value: u64, bps: u32) -> Option<i64> {
+    i64::try_from((u128::from(value) * u128::from(bps)).div_ceil(u128::from(BPS))).ok()
+}
+
+fn bps_floor(value: u64, bps: u32) -> Option<i64> {
+    i64::try_from(u128::from(value) * u128::from(bps) / u128::from(BPS)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::orderbook::{
+        fees::{FeeLine, FeeSchedule},
+        order::{Order, OrderSide},
+        OrderBook,
+    };
+
+    #[test]
+    fn test_fees_and_uneconomic_fills() {
+        let schedule = FeeSchedule {
+            maker_bps: 10,
+            taker_bps: 50,
+            min_fee: 2,
+            maker_rebate_bps: 20,
+            max_fee_bps: Some(100),
+        };
+        let mut book = OrderBook::new().with_fee_schedule(Some(schedule));
+        book.add_order(Order::new(1, 0, 1000, 40, OrderSide::Sell));
+        book.add_order(Order::new(2, 0, 1000, 40, OrderSide::Buy));
+        // 1 at 40 would pay the minimum fee of 2, 5% of the fill
+        book.add_order(Order::new(3, 0, 1, 50, OrderSide::Sell));
+        book.add_order(Order::new(4, 0, 1, 50, OrderSide::Buy));
+
+        let result = book.run_matching();
+        assert_eq!(result.fills.len(), 1);
+        let fees = result.fills[0].fees.unwrap();
+        // 40000 * 0.5% to the taker, 0.1% minus a 0.2% rebate to the maker
+        assert_eq!(
+            fees.taker,
+            FeeLine {
+                gross: 40_000,
+                fee: 200,
+                net: 40_200
+            }
+        );
+        assert_eq!(
+            fees.maker,
+            FeeLine {
+                gross: 40_000,
+                fee: -40,
+                net: 40_040
+            }
+        );
+        assert_eq!(result.revenue(), 160);
+
+        // without a maximum the small fill is made
+        let book = book.with_fee_schedule(Some(FeeSchedule {
+            max_fee_bps: None,
+            ..schedule
+        }));
+        assert_eq!(book.run_matching().fills.len(), 2);
+        assert!(OrderBook::new().run_matching().fills.is_empty());
+
+        // fees too large to account for are never charged
+        let book = book.with_fee_schedule(Some(FeeSchedule {
+            min_fee: u64::MAX,
+            max_fee_bps: None,
+            ..schedule
+        }));
+        assert!(book.run_matching().fills.is_empty());
+    }
+}
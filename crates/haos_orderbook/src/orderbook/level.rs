@@ -0,0 +1,145 @@
+use std::collections::{btree_map, BTreeMap};
+
+use super::{
+    order::{Order, OrderSide},
+    types::{OrderId, Price},
+};
+
+/// Orders resting at a single price, kept in time priority.
+///
+/// On-chain order ids are assigned sequentially, so the lowest id is the
+/// oldest order and is first in the queue.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PriceLevel {
+    orders: BTreeMap<OrderId, Order>,
+    volume: u64,
+}
+
+impl PriceLevel {
+    pub fn insert(&mut self, order: Order) {
+        self.volume += u64::from(order.volume);
+        if let Some(previous) = self.orders.insert(order.id, order) {
+            self.volume -= u64::from(previous.volume);
+        }
+    }
+
+    pub fn remove(&mut self, id: OrderId) -> Option<Order> {
+        let order = self.orders.remove(&id)?;
+        self.volume -= u64::from(order.volume);
+        Some(order)
+    }
+
+    pub fn get(&self, id: OrderId) -> Option<&Order> {
+        self.orders.get(&id)
+    }
+
+    /// The order with the highest time priority at this level.
+    pub fn front(&self) -> Option<&Order> {
+        self.orders.values().next()
+    }
+
+    /// Orders at this level in time priority.
+    pub fn iter(&self) -> btree_map::Values<'_, OrderId, Order> {
+        self.orders.values()
+    }
+
+    /// Total resting volume at this level.
+    pub fn volume(&self) -> u64 {
+        self.volume
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+}
+
+/// One side of the book, with price levels sorted so that the best price
+/// can be found in O(log n).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookSide {
+    side: OrderSide,
+    levels: BTreeMap<Price, PriceLevel>,
+    len: usize,
+}
+
+impl BookSide {
+    pub fn new(side: OrderSide) -> Self {
+        BookSide {
+            side,
+            levels: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn side(&self) -> OrderSide {
+        self.side
+    }
+
+    pub fn insert(&mut self, order: Order) {
+        let level = self.levels.entry(order.price).or_default();
+        let before = level.len();
+        level.insert(order);
+        self.len += level.len() - before;
+    }
+
+    pub fn remove(&mut self, price: Price, id: OrderId) -> Option<Order> {
+        let btree_map::Entry::Occupied(mut level) = self.levels.entry(price) else {
+            return None;
+        };
+        let order = level.get_mut().remove(id)?;
+        if level.get().is_empty() {
+            level.remove();
+        }
+        self.len -= 1;
+        Some(order)
+    }
+
+    pub fn get(&self, price: Price, id: OrderId) -> Option<&Order> {
+        self.levels.get(&price)?.get(id)
+    }
+
+    /// The best price level: highest price for buys, lowest for sells.
+    pub fn best_level(&self) -> Option<(Price, &PriceLevel)> {
+        let level = match self.side {
+            OrderSide::Buy => self.levels.iter().next_back(),
+            OrderSide::Sell => self.levels.iter().next(),
+        };
+        level.map(|(price, level)| (*price, level))
+    }
+
+    /// The order with the highest price-time priority on this side.
+    pub fn best(&self) -> Option<&Order> {
+        self.best_level().and_then(|(_, level)| level.front())
+    }
+
+    /// Price levels from the best price outwards.
+    pub fn levels(&self) -> impl Iterator<Item = (Price, &PriceLevel)> + Clone + '_ {
+        let (ascending, descending) = match self.side {
+            OrderSide::Buy => (None, Some(self.levels.iter().rev())),
+            OrderSide::Sell => (Some(self.levels.iter()), None),
+        };
+        ascending
+            .into_iter()
+            .flatten()
+            .chain(descending.into_iter().flatten())
+            .map(|(price, level)| (*price, level))
+    }
+
+    /// Orders in price-time priority.
+    pub fn orders(&self) -> impl Iterator<Item = &Order> + Clone + '_ {
+        self.levels().flat_map(|(_, level)| level.iter())
+    }
+
+    /// Number of resting orders on this side.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
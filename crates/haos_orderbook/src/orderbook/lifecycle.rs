@@ -0,0 +1,105 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::order::Outcome;
+
+/// Where a market is in its life, from trading to resolution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketState {
+    /// Orders are accepted and matched.
+    #[default]
+    Open,
+    /// Matching is paused, the book keeps tracking order updates.
+    Halted,
+    /// No new orders are accepted, the resting ones are still matched.
+    Closing,
+    /// The Resolver settled the event, nothing is matched anymore.
+    Resolved(Outcome),
+}
+
+impl MarketState {
+    pub fn is_matching(&self) -> bool {
+        matches!(self, MarketState::Open | MarketState::Closing)
+    }
+
+    /// Whether orders not yet in the book may enter it.
+    pub fn accepts_new_orders(&self) -> bool {
+        matches!(self, MarketState::Open | MarketState::Halted)
+    }
+
+    /// Moves to `next`. A market can be halted and resumed until it starts
+    /// closing, and a resolved market stays resolved.
+    pub fn transition(self, next: MarketState) -> anyhow::Result<MarketState> {
+        let allowed = match (self, next) {
+            (MarketState::Resolved(_), _) => false,
+            (_, MarketState::Resolved(_)) => true,
+            (MarketState::Open, MarketState::Halted | MarketState::Closing) => true,
+            (MarketState::Halted, MarketState::Open | MarketState::Closing) => true,
+            _ => false,
+        };
+        if allowed {
+            Ok(next)
+        } else {
+            Err(anyhow::anyhow!(
+                "Market cannot move from {} to {}",
+                self,
+                next
+            ))
+        }
+    }
+}
+
+impl fmt::Display for MarketState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarketState::Open => write!(f, "open"),
+            MarketState::Halted => write!(f, "halted"),
+            MarketState::Closing => write!(f, "closing"),
+            MarketState::Resolved(Outcome::Yes) => write!(f, "resolved_yes"),
+            MarketState::Resolved(Outcome::No) => write!(f, "resolved_no"),
+        }
+    }
+}
+
+impl FromStr for MarketState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(MarketState::Open),
+            "halted" => Ok(MarketState::Halted),
+            "closing" => Ok(MarketState::Closing),
+            "resolved_yes" => Ok(MarketState::Resolved(Outcome::Yes)),
+            "resolved_no" => Ok(MarketState::Resolved(Outcome::No)),
+            _ => Err(anyhow::anyhow!("Unknown market state: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::orderbook::{lifecycle::MarketState, order::Outcome};
+
+    #[test]
+    fn test_state_transitions() {
+        let state = MarketState::Open;
+        let state = state.transition(MarketState::Halted).unwrap();
+        assert!(!state.is_matching());
+        assert!(state.accepts_new_orders());
+        let state = state.transition(MarketState::Open).unwrap();
+        let state = state.transition(MarketState::Closing).unwrap();
+        assert!(state.is_matching());
+        assert!(!state.accepts_new_orders());
+        assert!(state.transition(MarketState::Open).is_err());
+
+        let resolved = MarketState::Resolved(Outcome::No);
+        let state = state.transition(resolved).unwrap();
+        assert!(!state.is_matching());
+        assert!(state.transition(MarketState::Open).is_err());
+        assert!(state.transition(resolved).is_err());
+        assert_eq!("resolved_no".parse::<MarketState>().unwrap(), resolved);
+        assert_eq!(resolved.to_string(), "resolved_no");
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{BTreeMap, HashMap};
+
+use alloy::primitives::Address;
+use anyhow::Result;
+use tracing::warn;
+
+use super::{lifecycle::MarketState, order::Order, Fill, MatchedOrders, MatchingResult, OrderBook};
+use crate::{
+    config::MarketConfig,
+    prices::{PriceDiscovery, Trade},
+    snapshot::MarketSnapshot,
+};
+
+/// The order book of a single market together with the contract it settles on.
+#[derive(Clone, Debug)]
+pub struct MarketBook {
+    pub config: MarketConfig,
+    pub book: OrderBook,
+    pub prices: PriceDiscovery,
+    // block of the last batch auction
+    last_auction_block: u64,
+    state: MarketState,
+    // block a market halted by its circuit breaker resumes at
+    resume_block: Option<u64>,
+}
+
+impl MarketBook {
+    /// An empty, open market settled on chain as `config` sets it up.
+    pub fn from_config(config: MarketConfig) -> Self {
+        let book = OrderBook::new()
+            .with_self_trade_prevention(config.self_trade_prevention)
+            .with_matching_policy(config.matching_policy)
+            .with_contract_variant(config.contract_variant)
+            .with_on_chain_settlement(true)
+            .with_fee_schedule(config.fee_schedule)
+            .with_price_band(config.price_band);
+        let prices = PriceDiscovery::new(book.unit_price());
+        MarketBook {
+            config,
+            book,
+            prices,
+            last_auction_block: 0,
+            state: MarketState::default(),
+            resume_block: None,
+        }
+    }
+
+    /// Whether a batch auction is due at `block_number`. Markets that match
+    /// continuously or are not matching never hold one.
+    pub fn is_auction_due(&self, block_number: u64) -> bool {
+        self.state.is_matching()
+            && self
+                .config
+                .auction_interval
+                .is_some_and(|interval| block_number >= self.last_auction_block + interval)
+    }
+
+    pub fn state(&self) -> MarketState {
+        self.state
+    }
+
+    /// Moves the market to `state`, failing if its lifecycle does not allow it.
+    pub fn set_state(&mut self, state: MarketState) -> Result<()> {
+        self.state = self.state.transition(state)?;
+        self.resume_block = None;
+        Ok(())
+    }
+
+    /// Records a confirmed trade. The Yes price it implies is the reference
+    /// of the price band.
+    pub fn record_trade(&mut self, trade: Trade) {
+        self.prices.record(trade);
+        self.book
+            .set_last_price(self.prices.yes_price().map(Into::into));
+    }
+
+    /// Forgets the trades a reorg orphaned after `block_number`.
+    pub fn rollback_trades(&mut self, block_number: u64) {
+        self.prices.rollback(block_number);
+        self.book
+            .set_last_price(self.prices.yes_price().map(Into::into));
+    }
+
+    /// Puts the market back in a state it left in blocks a reorg orphaned,
+    /// whatever the allowed transitions.
+    pub fn revert_state(&mut self, state: MarketState) {
+        self.state = state;
+        self.resume_block = None;
+    }
+
+    /// Halts an open market whose circuit breaker trips at `block_number`.
+    /// Returns whether the market was halted.
+    pub fn check_circuit_breaker(&mut self, block_number: u64) -> bool {
+        let Some(breaker) = self.config.circuit_breaker else {
+            return false;
+        };
+        if self.state != MarketState::Open || !breaker.is_tripped(&self.prices, block_number) {
+            return false;
+        }
+        self.state = MarketState::Halted;
+        self.resume_block = breaker.halt_blocks.map(|blocks| block_number + blocks);
+        true
+    }
+
+    /// Reopens a market halted by its circuit breaker once the halt is over.
+    /// Returns whether the market was reopened.
+    pub fn resume_after_halt(&mut self, block_number: u64) -> bool {
+        if self.state != MarketState::Halted
+            || !self
+                .resume_block
+                .is_some_and(|resume_block| block_number >= resume_block)
+        {
+            return false;
+        }
+        self.state = MarketState::Open;
+        self.resume_block = None;
+        true
+    }
+
+    pub fn snapshot(&self) -> MarketSnapshot {
+        MarketSnapshot {
+            contract_id: self.config.contract_id,
+            address: self.config.address,
+            block_number: self.book.block_number(),
+            state: self.state,
+            resume_block: self.resume_block,
+            orders: self.book.orders().cloned().collect(),
+            trades: self.prices.trades().cloned().collect(),
+        }
+    }
+
+    /// Replaces the book and price history with the snapshot's.
+    pub fn restore(&mut self, snapshot: MarketSnapshot) {
+        *self = MarketBook {
+            last_auction_block: self.last_auction_block,
+            ..MarketBook::from_config(self.config.clone())
+        };
+        self.book.set_block_number(snapshot.block_number);
+        self.state = snapshot.state;
+        self.resume_block = snapshot.resume_block;
+        for order in snapshot.orders {
+            self.book.add_order(order);
+        }
+        for trade in snapshot.trades {
+            self.record_trade(trade);
+        }
+    }
+}
+
+/// Registry of order books, one per market, keyed by `Order::contract_id`.
+#[derive(Clone, Debug, Default)]
+pub struct MarketBooks {
+    markets: BTreeMap<u32, MarketBook>,
+    addresses: HashMap<Address, u32>,
+}
+
+impl MarketBooks {
+    pub fn new(markets: impl IntoIterator<Item = MarketConfig>) -> Self {
+        let mut books = MarketBooks::default();
+        for config in markets {
+            books.add_market(config);
+        }
+        books
+    }
+
+    pub fn add_market(&mut self, config: MarketConfig) {
+        self.addresses.insert(config.address, config.contract_id);
+        self.markets
+            .insert(config.contract_id, MarketBook::from_config(config));
+    }
+
+    pub fn market(&self, contract_id: u32) -> Option<&MarketBook> {
+        self.markets.get(&contract_id)
+    }
+
+    pub fn market_mut(&mut self, contract_id: u32) -> Option<&mut MarketBook> {
+        self.markets.get_mut(&contract_id)
+    }
+
+    pub fn market_by_address(&self, address: Address) -> Option<&MarketBook> {
+        self.market(*self.addresses.get(&address)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MarketBook> + '_ {
+        self.markets.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut MarketBook> + '_ {
+        self.markets.values_mut()
+    }
+
+    // route an order to the book of its market
+    pub fn update_order(&mut self, order: Order) -> bool {
+        match self.market_mut(order.contract_id) {
+            Some(market) => market.book.update_order(order),
+            None => {
+                warn!(
+                    "Order {} for unknown market {}",
+                    order.id, order.contract_id
+                );
+                false
+            }
+        }
+    }
+
+    pub fn set_block_number(&mut self, block_number: u64) {
+        for market in self.markets.values_mut() {
+            market.book.set_block_number(block_number);
+        }
+    }
+
+    // run a matching pass in each continuous market that is matching, skipping
+    // markets with nothing to do
+    pub fn run_matching(&self) -> Vec<(u32, MatchingResult)> {
+        self.markets
+            .iter()
+            .filter(|(_, market)| {
+                market.config.auction_interval.is_none() && market.state.is_matching()
+            })
+            .map(|(contract_id, market)| (*contract_id, market.book.run_matching()))
+            .filter(|(_, result)| {
+                !result.fills.is_empty() || !result.cancelled.is_empty() || !result.held.is_empty()
+            })
+            .collect()
+    }
+
+    /// Runs the batch auctions due at `block_number` in the markets accepted
+    /// by `ready`. Markets that are not ready hold theirs once they are.
+    pub fn run_auctions(
+        &mut self,
+        block_number: u64,
+        ready: impl Fn(u32) -> bool,
+    ) -> Vec<(u32, MatchingResult)> {
+        self.markets
+            .iter_mut()
+            .filter(|(contract_id, market)| {
+                market.is_auction_due(block_number) && ready(**contract_id)
+            })
+            .map(|(contract_id, market)| {
+                market.last_auction_block = block_number;
+                (*contract_id, market.book.run_auction())
+            })
+            .filter(|(_, result)| {
+                !result.fills.is_empty() || !result.cancelled.is_empty() || !result.held.is_empty()
+            })
+            .collect()
+    }
+
+    // find all fills that uncross each market
+    pub fn find_all_matching_orders(&self) -> Vec<(u32, Vec<Fill>)> {
+        self.markets
+            .iter()
+            .map(|(contract_id, market)| (*contract_id, market.book.find_all_matching_orders()))
+            .filter(|(_, fills)| !fills.is_empty())
+            .collect()
+    }
+
+    // find a match in every market, each market is matched on its own
+    pub fn find_matching_orders(&self) -> Vec<(u32, MatchedOrders)> {
+        self.markets
+            .iter()
+            .filter_map(|(contract_id, market)| {
+                market
+                    .book
+                    .find_matching_orders()
+                    .map(|matched| (*contract_id, matched))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Address;
+
+    use crate::{
+        config::MarketConfig,
+        orderbook::{
+            lifecycle::MarketState,
+            markets::MarketBooks,
+            order::{Order, OrderSide},
+        },
+    };
+
+    #[test]
+    fn test_markets_match_independently() {
+        let mut books = MarketBooks::new([
+            MarketConfig::new(0, Address::repeat_byte(1)),
+            MarketConfig::new(1, Address::repeat_byte(2)),
+        ]);
+
+        // same order ids in different markets do not interfere
+        assert!(books.update_order(Order::new(1, 0, 10, 11, OrderSide::Buy)));
+        assert!(books.update_order(Order::new(1, 1, 10, 12, OrderSide::Sell)));
+        assert!(books.update_order(Order::new(2, 1, 10, 12, OrderSide::Buy)));
+        assert!(!books.update_order(Order::new(1, 7, 10, 12, OrderSide::Buy)));
+
+        let matches = books.find_matching_orders();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, 1);
+        assert_eq!(matches[0].1.maker_order_id, 1.into());
+        assert_eq!(matches[0].1.taker_order_id, 2.into());
+
+        let market = books.market_by_address(Address::repeat_byte(1)).unwrap();
+        assert_eq!(market.book.len(), 1);
+
+        // a halted market is not matched
+        assert_eq!(books.run_matching().len(), 1);
+        books
+            .market_mut(1)
+            .unwrap()
+            .set_state(MarketState::Halted)
+            .unwrap();
+        assert!(books.run_matching().is_empty());
+    }
+}
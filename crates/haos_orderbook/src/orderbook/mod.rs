@@ -1,73 +1,310 @@
+pub mod auction;
+pub mod bands;
+pub mod depth;
+pub mod execution;
+pub mod fees;
+pub mod level;
+pub mod lifecycle;
+pub mod markets;
 pub mod order;
-use std::{cmp::Ordering, collections::BinaryHeap};
+pub mod policy;
+pub mod types;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    str::FromStr,
+};
 
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
-#[derive(Clone, Debug)]
+use self::{
+    bands::PriceBand,
+    execution::{ContractVariant, Execution},
+    fees::{FeeSchedule, FillFees},
+    level::{BookSide, PriceLevel},
+    order::{Order, OrderSide, Outcome, TimeInForce},
+    policy::{LevelQueue, MatchingPolicy},
+    types::{OrderId, Price, Quantity},
+};
+use crate::constants::UNIT_PRICE;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct OrderBook {
-    buy_orders: BinaryHeap<order::Order>,
-    sell_orders: BinaryHeap<order::Order>,
+    // one book side per outcome and order side, see `slot`
+    sides: [BookSide; 4],
+    // order id -> (outcome, side, price), used to locate an order's price level
+    index: HashMap<OrderId, (Outcome, OrderSide, Price)>,
+    // immediate-or-cancel and fill-or-kill orders in the book
+    immediate_orders: BTreeSet<OrderId>,
+    // (expiry block, order id) of good-till-block orders
+    expiries: BTreeSet<(u64, OrderId)>,
+    unit_price: u32,
+    self_trade_prevention: SelfTradePrevention,
+    matching_policy: MatchingPolicy,
+    contract_variant: ContractVariant,
+    // fills are settled by the contract, so only those it can settle are made
+    on_chain: bool,
+    fee_schedule: Option<FeeSchedule>,
+    price_band: Option<PriceBand>,
+    // Yes price implied by the last confirmed trade, the default reference of
+    // the band. The No band is centred on its complement
+    last_price: Option<Price>,
+    // block the book is matched at, used to expire good-till-block orders
+    block_number: u64,
+}
+
+/// What to do when both orders of a match were placed by the same creator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    /// Match them like any other pair.
+    Allow,
+    /// Cancel the newer order.
+    CancelNewest,
+    /// Cancel the older order.
+    CancelOldest,
+    /// Keep both in the book, the newer order sits out the rest of the pass.
+    #[default]
+    Skip,
+}
+
+impl FromStr for SelfTradePrevention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(SelfTradePrevention::Allow),
+            "cancel_newest" => Ok(SelfTradePrevention::CancelNewest),
+            "cancel_oldest" => Ok(SelfTradePrevention::CancelOldest),
+            "skip" => Ok(SelfTradePrevention::Skip),
+            _ => Err(anyhow::anyhow!("Unknown self-trade prevention mode: {}", s)),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MatchedOrders {
-    pub taker_order_id: u32,
-    pub maker_order_id: u32,
+    pub taker_order_id: OrderId,
+    pub maker_order_id: OrderId,
+    /// Price `matchOrders` is expected to execute at, if it moves tokens.
+    #[serde(default)]
+    pub expected_price: Option<Price>,
+    /// Volume `matchOrders` is expected to fill.
+    #[serde(default)]
+    pub expected_volume: Option<Quantity>,
+}
+
+impl MatchedOrders {
+    pub fn new(taker_order_id: OrderId, maker_order_id: OrderId) -> Self {
+        MatchedOrders {
+            taker_order_id,
+            maker_order_id,
+            expected_price: None,
+            expected_volume: None,
+        }
+    }
+
+    pub fn with_execution(mut self, execution: &Execution) -> Self {
+        self.expected_price = execution.price;
+        self.expected_volume = Some(execution.volume);
+        self
+    }
+
+    /// Whether both refer to the same taker and maker order.
+    pub fn is_same_pair(&self, other: &MatchedOrders) -> bool {
+        self.taker_order_id == other.taker_order_id && self.maker_order_id == other.maker_order_id
+    }
+}
+
+/// How a fill is settled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchKind {
+    /// A buy and a sell of the same outcome token.
+    Direct,
+    /// A Yes buy and a No buy, paid for by minting a complete set.
+    Mint,
+    /// A Yes sell and a No sell, paid out by burning a complete set.
+    Burn,
+}
+
+/// A single execution between two crossing orders, as modelled locally.
+///
+/// For mint and burn fills `price` is the maker's price for its own outcome,
+/// the taker trades the complementary outcome at the unit price minus `price`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fill {
+    pub kind: MatchKind,
+    pub taker_order_id: OrderId,
+    pub maker_order_id: OrderId,
+    pub price: Price,
+    pub volume: Quantity,
+    /// Outcome of the maker order, the outcome `price` is quoted in.
+    #[serde(default)]
+    pub outcome: Outcome,
+    /// Fees of the fill, if the market charges any.
+    #[serde(default)]
+    pub fees: Option<FillFees>,
+}
+
+impl Fill {
+    // the order with the lower id is the maker order, its price is used
+    // unless the contract executes the fill at another
+    fn new(kind: MatchKind, first: &Order, second: &Order, volume: Quantity) -> Self {
+        let (maker, taker) = if first.id < second.id {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        Fill {
+            kind,
+            taker_order_id: taker.id,
+            maker_order_id: maker.id,
+            price: maker.price,
+            volume,
+            outcome: maker.outcome,
+            fees: None,
+        }
+    }
+
+    pub fn matched_orders(&self) -> MatchedOrders {
+        MatchedOrders::new(self.taker_order_id, self.maker_order_id)
+    }
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OrderBook {
     pub fn new() -> Self {
+        Self::with_unit_price(UNIT_PRICE)
+    }
+
+    pub fn with_unit_price(unit_price: u32) -> Self {
         OrderBook {
-            buy_orders: BinaryHeap::new(),
-            sell_orders: BinaryHeap::new(),
+            sides: [
+                BookSide::new(OrderSide::Buy),
+                BookSide::new(OrderSide::Sell),
+                BookSide::new(OrderSide::Buy),
+                BookSide::new(OrderSide::Sell),
+            ],
+            index: HashMap::new(),
+            immediate_orders: BTreeSet::new(),
+            expiries: BTreeSet::new(),
+            unit_price,
+            self_trade_prevention: SelfTradePrevention::default(),
+            matching_policy: MatchingPolicy::default(),
+            contract_variant: ContractVariant::default(),
+            on_chain: false,
+            fee_schedule: None,
+            price_band: None,
+            last_price: None,
+            block_number: 0,
         }
     }
 
-    pub fn add_order(&mut self, order: order::Order) -> bool {
-        if order.volume == 0 {
-            return false;
-        }
-        match order.side {
-            order::OrderSide::Buy => {
-                self.buy_orders.push(order);
-            }
-            order::OrderSide::Sell => {
-                self.sell_orders.push(order);
-            }
-        }
-        true
+    pub fn with_self_trade_prevention(
+        mut self,
+        self_trade_prevention: SelfTradePrevention,
+    ) -> Self {
+        self.self_trade_prevention = self_trade_prevention;
+        self
+    }
+
+    pub fn with_matching_policy(mut self, matching_policy: MatchingPolicy) -> Self {
+        self.matching_policy = matching_policy;
+        self
     }
 
-    pub fn remove_order(&mut self, id: u32) -> bool {
-        let mut found = false;
+    pub fn with_contract_variant(mut self, contract_variant: ContractVariant) -> Self {
+        self.contract_variant = contract_variant;
+        self
+    }
 
-        // remove the order from the buy orders
-        let mut buy_orders = Vec::new();
-        while let Some(order) = self.buy_orders.pop() {
-            if order.id == id {
-                found = true;
-            } else {
-                buy_orders.push(order);
-            }
+    pub fn with_on_chain_settlement(mut self, on_chain: bool) -> Self {
+        self.on_chain = on_chain;
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, fee_schedule: Option<FeeSchedule>) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    pub fn with_price_band(mut self, price_band: Option<PriceBand>) -> Self {
+        self.price_band = price_band;
+        self
+    }
+
+    /// Sets the Yes price the price band is centred on when it has no fixed
+    /// reference.
+    pub fn set_last_price(&mut self, last_price: Option<Price>) {
+        self.last_price = last_price;
+    }
+
+    pub fn last_price(&self) -> Option<Price> {
+        self.last_price
+    }
+
+    /// Lowest and highest price fills of `outcome` may be made at. References
+    /// are Yes prices, the No band is centred on the unit price minus it.
+    pub fn price_band(&self, outcome: Outcome) -> Option<(Price, Price)> {
+        let band = self.price_band?;
+        let reference = band.reference.or(self.last_price)?;
+        Some(band.range(match outcome {
+            Outcome::Yes => reference,
+            Outcome::No => self.unit_price.saturating_sub(reference.get()).into(),
+        }))
+    }
+
+    pub fn unit_price(&self) -> u32 {
+        self.unit_price
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    pub fn set_block_number(&mut self, block_number: u64) {
+        self.block_number = block_number;
+    }
+
+    pub fn add_order(&mut self, order: Order) -> bool {
+        if order.volume.is_zero() {
+            return false;
         }
-        self.buy_orders = BinaryHeap::from(buy_orders);
+        // an id can only rest once in the book
+        self.remove_order(order.id);
 
-        // remove the order from the sell orders
-        let mut sell_orders = Vec::new();
-        while let Some(order) = self.sell_orders.pop() {
-            if order.id == id {
-                found = true;
-            } else {
-                sell_orders.push(order);
-            }
+        self.index
+            .insert(order.id, (order.outcome, order.side, order.price));
+        if order.is_immediate() {
+            self.immediate_orders.insert(order.id);
         }
-        self.sell_orders = BinaryHeap::from(sell_orders);
+        if let Some(expiry_block) = order.expiry_block {
+            self.expiries.insert((expiry_block, order.id));
+        }
+        self.side_mut(order.outcome, order.side).insert(order);
+        true
+    }
 
-        found
+    pub fn remove_order(&mut self, id: OrderId) -> bool {
+        let Some((outcome, side, price)) = self.index.remove(&id) else {
+            return false;
+        };
+        let Some(order) = self.side_mut(outcome, side).remove(price, id) else {
+            return false;
+        };
+        self.immediate_orders.remove(&id);
+        if let Some(expiry_block) = order.expiry_block {
+            self.expiries.remove(&(expiry_block, id));
+        }
+        true
     }
 
     // update an order in the orderbook
-    pub fn update_order(&mut self, order: order::Order) -> bool {
+    pub fn update_order(&mut self, order: Order) -> bool {
         // if the order is not in the orderbook, return false
         if !self.remove_order(order.id) {
             info!("Order not found in orderbook, creating new order");
@@ -75,84 +312,632 @@ impl OrderBook {
             info!("Order found in orderbook, updating order");
         }
 
-        if order.volume > 0 {
+        if !order.volume.is_zero() {
             self.add_order(order);
         }
 
         true
     }
 
-    // find two orders that match
+    /// Removes `volume` from an order, dropping it from the book once it is
+    /// fully filled. Returns false if the order is not in the book.
+    pub fn reduce_order(&mut self, id: OrderId, volume: Quantity) -> bool {
+        let Some(order) = self.get_order(id).cloned() else {
+            return false;
+        };
+        self.remove_order(id);
+        self.add_order(Order {
+            volume: order.volume.saturating_sub(volume),
+            ..order
+        });
+        true
+    }
+
+    /// Predicts what the contract does when the two resting orders are
+    /// matched. Returns `None` if `matchOrders` would revert.
+    pub fn execute(&self, orders: &MatchedOrders) -> Option<Execution> {
+        let taker = self.get_order(orders.taker_order_id)?;
+        let maker = self.get_order(orders.maker_order_id)?;
+        self.contract_variant.execute(taker, maker)
+    }
+
+    /// The fill `matchOrders` makes of two resting orders. Where the contract
+    /// moves no tokens it is priced at the maker's price.
+    pub fn fill_for(&self, orders: &MatchedOrders) -> Option<Fill> {
+        let execution = self.execute(orders)?;
+        let maker = self.get_order(orders.maker_order_id)?;
+        Some(self.with_fees(Fill {
+            kind: MatchKind::Direct,
+            taker_order_id: orders.taker_order_id,
+            maker_order_id: orders.maker_order_id,
+            price: execution.price.unwrap_or(maker.price),
+            volume: execution.volume,
+            outcome: maker.outcome,
+            fees: None,
+        }))
+    }
+
+    /// Prices the fees of a fill between two resting orders.
+    pub fn with_fees(&self, fill: Fill) -> Fill {
+        let fees = self.fee_schedule.and_then(|schedule| {
+            let taker = self.get_order(fill.taker_order_id)?;
+            let maker = self.get_order(fill.maker_order_id)?;
+            schedule.fees(&fill, taker, maker, self.unit_price)
+        });
+        Fill { fees, ..fill }
+    }
+
+    // apply a fill to both orders, as matchOrders does on chain
+    pub fn apply_fill(&mut self, fill: &Fill) {
+        self.reduce_order(fill.taker_order_id, fill.volume);
+        self.reduce_order(fill.maker_order_id, fill.volume);
+    }
+
+    pub fn get_order(&self, id: OrderId) -> Option<&Order> {
+        let (outcome, side, price) = self.index.get(&id)?;
+        self.side(*outcome, *side).get(*price, id)
+    }
+
+    pub fn side(&self, outcome: Outcome, side: OrderSide) -> &BookSide {
+        &self.sides[Self::slot(outcome, side)]
+    }
+
+    fn side_mut(&mut self, outcome: Outcome, side: OrderSide) -> &mut BookSide {
+        &mut self.sides[Self::slot(outcome, side)]
+    }
+
+    fn slot(outcome: Outcome, side: OrderSide) -> usize {
+        let outcome = match outcome {
+            Outcome::Yes => 0,
+            Outcome::No => 2,
+        };
+        let side = match side {
+            OrderSide::Buy => 0,
+            OrderSide::Sell => 1,
+        };
+        outcome + side
+    }
+
+    /// All resting orders, in no particular order.
+    pub fn orders(&self) -> impl Iterator<Item = &Order> + '_ {
+        self.sides.iter().flat_map(|side| side.orders())
+    }
+
+    /// Number of resting orders on both sides.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    // find two orders of the same outcome that the contract can match
     pub fn find_matching_orders(&self) -> Option<MatchedOrders> {
-        let buy = self.buy_orders.peek();
-        let sell = self.sell_orders.peek();
-
-        match (buy, sell) {
-            (Some(buy), Some(sell)) => {
-                if buy.price >= sell.price {
-                    // the order with the lower id is the maker order
-                    if buy.id < sell.id {
-                        return Some(MatchedOrders {
-                            taker_order_id: sell.id,
-                            maker_order_id: buy.id,
-                        });
-                    }
-                    return Some(MatchedOrders {
-                        taker_order_id: buy.id,
-                        maker_order_id: sell.id,
-                    });
-                } else {
-                    None
-                }
+        self.run_matching()
+            .fills
+            .into_iter()
+            .filter(|fill| fill.kind == MatchKind::Direct)
+            .find_map(|fill| {
+                let orders = fill.matched_orders();
+                let execution = self.execute(&orders)?;
+                Some(orders.with_execution(&execution))
+            })
+    }
+
+    /// Walks the crossed part of the book and returns every fill needed to
+    /// uncross it, in execution order.
+    pub fn find_all_matching_orders(&self) -> Vec<Fill> {
+        self.run_matching().fills
+    }
+
+    /// Runs a matching pass over the whole book.
+    ///
+    /// Better prices match first, orders at the same price are prioritised
+    /// and allocated by the book's matching policy.
+    ///
+    /// Partial fills are tracked locally: each fill removes the smaller
+    /// order's volume from both orders, as `matchOrders` does on chain, and
+    /// the remainder of the larger order is matched against the next one.
+    ///
+    /// Each outcome is uncrossed directly first. What remains is matched
+    /// across outcomes: a Yes bid and a No bid that together pay at least the
+    /// unit price mint a complete set, and a Yes ask and a No ask that
+    /// together ask at most the unit price burn one. A book settled on chain
+    /// only makes the fills its contract can settle.
+    ///
+    /// Expired orders are skipped, post-only orders that would take are
+    /// refused and fill-or-kill orders only match if they fill completely.
+    /// Orders of the same creator are kept apart according to the book's
+    /// self-trade prevention mode. Fills that cost either order more in fees
+    /// than the fee schedule allows are skipped, and the order limiting the
+    /// fill sits out the rest of the pass. Direct fills priced outside the
+    /// price band are held back and reported, and the order furthest out
+    /// sits out the rest of the pass. Refused, killed and self-trade cancelled
+    /// orders, and whatever is left of immediate-or-cancel orders, are
+    /// reported as cancelled.
+    pub fn run_matching(&self) -> MatchingResult {
+        let mut state = self.match_state();
+        let mut fills = Vec::new();
+
+        for outcome in [Outcome::Yes, Outcome::No] {
+            if !self.settles(MatchKind::Direct, outcome) {
+                continue;
             }
-            _ => None,
+            fills.extend(state.cross(
+                MatchKind::Direct,
+                self.side(outcome, OrderSide::Buy),
+                self.side(outcome, OrderSide::Sell),
+                |buy, sell| buy.price >= sell.price,
+            ));
         }
+        if self.settles(MatchKind::Mint, Outcome::Yes) {
+            fills.extend(state.cross(
+                MatchKind::Mint,
+                self.side(Outcome::Yes, OrderSide::Buy),
+                self.side(Outcome::No, OrderSide::Buy),
+                |yes, no| u64::from(yes.price) + u64::from(no.price) >= u64::from(self.unit_price),
+            ));
+        }
+        if self.settles(MatchKind::Burn, Outcome::Yes) {
+            fills.extend(state.cross(
+                MatchKind::Burn,
+                self.side(Outcome::Yes, OrderSide::Sell),
+                self.side(Outcome::No, OrderSide::Sell),
+                |yes, no| u64::from(yes.price) + u64::from(no.price) <= u64::from(self.unit_price),
+            ));
+        }
+        self.matching_result(state, fills)
     }
-}
 
-// Implement Ord for Order to define the sorting behavior
-impl Ord for order::Order {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match (self.side, other.side) {
-            // buy orders are sorted by price, then by id
-            // first in the heap is the highest price, then the lowest id to ensure FIFO
-            (order::OrderSide::Buy, order::OrderSide::Buy) => self
-                .price
-                .cmp(&other.price)
-                .then_with(|| other.id.cmp(&self.id)),
-            // sell orders are sorted in reverse
-            // first in the heap is the lowest price, then the lowest id to ensure FIFO
-            (order::OrderSide::Sell, order::OrderSide::Sell) => other
-                .price
-                .cmp(&self.price)
-                .then_with(|| other.id.cmp(&self.id)),
-            _ => panic!("Cannot compare buy and sell orders, should be separate heaps"),
+    // whether the book makes fills of `kind` between orders of `outcome`
+    fn settles(&self, kind: MatchKind, outcome: Outcome) -> bool {
+        !self.on_chain || self.contract_variant.settles(kind, outcome)
+    }
+
+    fn match_state(&self) -> MatchState {
+        MatchState {
+            block_number: self.block_number,
+            self_trade_prevention: self.self_trade_prevention,
+            matching_policy: self.matching_policy,
+            contract_variant: self.contract_variant,
+            fee_schedule: self.fee_schedule,
+            unit_price: self.unit_price,
+            clearing_price: None,
+            bands: [self.price_band(Outcome::Yes), self.price_band(Outcome::No)],
+            held: Vec::new(),
+            filled: HashMap::new(),
+            approved: HashSet::new(),
+            cancelled: BTreeSet::new(),
+            allocation: None,
+        }
+    }
+
+    // adds the orders that leave the book after a pass to its cancellations
+    fn matching_result(&self, mut state: MatchState, fills: Vec<Fill>) -> MatchingResult {
+        let mut cancelled = std::mem::take(&mut state.cancelled);
+        cancelled.extend(
+            self.expiries
+                .range(..(self.block_number, OrderId::ZERO))
+                .map(|(_, id)| *id),
+        );
+        cancelled.extend(self.immediate_orders.iter().copied().filter(|id| {
+            self.get_order(*id)
+                .is_some_and(|order| !state.remaining(order).is_zero())
+        }));
+
+        MatchingResult {
+            fills,
+            cancelled: cancelled.into_iter().collect(),
+            held: state.held,
         }
     }
+
+    /// Removes orders reported as cancelled by a matching pass.
+    pub fn cancel_orders(&mut self, ids: &[OrderId]) {
+        for id in ids {
+            if self.remove_order(*id) {
+                info!("Order {} cancelled", id);
+            }
+        }
+    }
+}
+
+/// Outcome of a matching pass.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MatchingResult {
+    /// Fills in execution order.
+    pub fills: Vec<Fill>,
+    /// Orders that should leave the book without being filled further.
+    pub cancelled: Vec<OrderId>,
+    /// Fills outside the price band, held back instead of being settled.
+    pub held: Vec<Fill>,
 }
 
-// PartialOrd is required by Ord
-impl PartialOrd for order::Order {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl MatchingResult {
+    /// Fees the matcher keeps from the fills.
+    pub fn revenue(&self) -> i64 {
+        self.fills
+            .iter()
+            .filter_map(|fill| fill.fees)
+            .map(|fees| fees.revenue())
+            .sum()
     }
 }
 
-// PartialEq is required by BinaryHeap
-impl PartialEq for order::Order {
-    fn eq(&self, other: &Self) -> bool {
-        self.price == other.price
+/// Bookkeeping of a single matching pass.
+struct MatchState {
+    block_number: u64,
+    self_trade_prevention: SelfTradePrevention,
+    matching_policy: MatchingPolicy,
+    contract_variant: ContractVariant,
+    fee_schedule: Option<FeeSchedule>,
+    unit_price: u32,
+    // uniform price of an auction, where no order takes, so post-only orders
+    // are not refused. Fills are priced at it where the contract moves no tokens
+    clearing_price: Option<Price>,
+    // lowest and highest price direct fills of the Yes and of the No outcome
+    // may be made at
+    bands: [Option<(Price, Price)>; 2],
+    held: Vec<Fill>,
+    // volume taken from each order by fills earlier in the pass
+    filled: HashMap<OrderId, Quantity>,
+    // fill-or-kill orders known to fill completely
+    approved: HashSet<OrderId>,
+    cancelled: BTreeSet<OrderId>,
+    // an order's volume split across the opposite price level, as
+    // (order id, counterparty id -> share not yet filled)
+    allocation: Option<(OrderId, HashMap<OrderId, Quantity>)>,
+}
+
+/// Walks one book side a price level at a time, in the priority of the
+/// matching policy.
+struct Cursor<'a, L> {
+    levels: L,
+    // orders of the current level not yet taken from the level queue
+    level: Option<LevelQueue<'a>>,
+    // orders taken from the level queue that are not done yet
+    queue: VecDeque<&'a Order>,
+    // orders of the current level that got their share of the allocated
+    // order, they are queued again for the next one
+    passed: Vec<&'a Order>,
+}
+
+impl<'a, L: Iterator<Item = (Price, &'a PriceLevel)> + Clone> Cursor<'a, L> {
+    fn new(levels: L) -> Self {
+        Cursor {
+            levels,
+            level: None,
+            queue: VecDeque::new(),
+            passed: Vec::new(),
+        }
+    }
+
+    /// The first active order. The next price level is only loaded once
+    /// every order of the current one is done.
+    fn current(&mut self, state: &MatchState) -> Option<&'a Order> {
+        loop {
+            while let Some(&order) = self.queue.front() {
+                if state.is_active(order) {
+                    return Some(order);
+                }
+                self.queue.pop_front();
+            }
+            if let Some(order) = self.level.as_mut().and_then(Iterator::next) {
+                self.queue.push_back(order);
+                continue;
+            }
+            if !self.passed.is_empty() {
+                return None;
+            }
+            let (_, level) = self.levels.next()?;
+            self.level = Some(
+                state
+                    .matching_policy
+                    .queue(level, |order| state.remaining(order)),
+            );
+        }
+    }
+
+    fn advance(&mut self) {
+        self.queue.pop_front();
+    }
+
+    fn pass(&mut self) {
+        self.passed.extend(self.queue.pop_front());
+    }
+
+    // queue the passed orders again, ahead of the rest of their level
+    fn restore(&mut self) -> bool {
+        let restored = !self.passed.is_empty();
+        for order in self.passed.drain(..).rev() {
+            self.queue.push_front(order);
+        }
+        restored
+    }
+
+    /// Orders of the current price level.
+    fn level(&self) -> impl Iterator<Item = &'a Order> + '_ {
+        self.passed
+            .iter()
+            .chain(self.queue.iter())
+            .copied()
+            .chain(self.level.clone().into_iter().flatten())
+    }
+
+    /// Orders after the current one.
+    fn rest(&self) -> impl Iterator<Item = &'a Order> + '_ {
+        self.queue
+            .iter()
+            .skip(1)
+            .chain(self.passed.iter())
+            .copied()
+            .chain(self.level.clone().into_iter().flatten())
+            .chain(self.levels.clone().flat_map(|(_, level)| level.iter()))
     }
 }
 
-// Eq is required by BinaryHeap when using PartialOrd
-impl Eq for order::Order {}
+impl MatchState {
+    fn remaining(&self, order: &Order) -> Quantity {
+        order.volume - self.filled.get(&order.id).copied().unwrap_or_default()
+    }
+
+    fn is_active(&self, order: &Order) -> bool {
+        !self.remaining(order).is_zero()
+            && !order.is_expired(self.block_number)
+            && !self.cancelled.contains(&order.id)
+    }
+
+    fn is_self_trade(&self, order: &Order, other: &Order) -> bool {
+        self.self_trade_prevention != SelfTradePrevention::Allow && order.has_same_creator(other)
+    }
+
+    /// Whether a fill-or-kill order can be filled completely by `counterparty`
+    /// and the active orders after it that still cross.
+    fn fills_completely<'a>(
+        &self,
+        order: &Order,
+        counterparty: &'a Order,
+        rest: impl Iterator<Item = &'a Order>,
+        crosses: impl Fn(&Order) -> bool,
+    ) -> bool {
+        let available = std::iter::once(counterparty)
+            .chain(rest.filter(|other| self.is_active(other)))
+            .take_while(|other| crosses(other))
+            .filter(|other| !self.is_self_trade(order, other))
+            .map(|other| u64::from(self.remaining(other)))
+            .sum::<u64>();
+        available >= u64::from(self.remaining(order))
+    }
+
+    // split the newer order's volume across the price level of the older one
+    fn allocate<'a, L: Iterator<Item = (Price, &'a PriceLevel)> + Clone>(
+        &self,
+        a_order: &Order,
+        b_order: &Order,
+        a_side: &Cursor<'a, L>,
+        b_side: &Cursor<'a, L>,
+    ) -> Option<(OrderId, HashMap<OrderId, Quantity>)> {
+        let (order, level) = if a_order.id > b_order.id {
+            (a_order, b_side.level())
+        } else {
+            (b_order, a_side.level())
+        };
+        let shares = self.matching_policy.allocate(
+            self.remaining(order),
+            level
+                .filter(|other| self.is_active(other) && !self.is_self_trade(order, other))
+                .map(|other| (other, self.remaining(other))),
+        )?;
+        Some((order.id, shares.into_iter().collect()))
+    }
+
+    fn fill(&self, kind: MatchKind, a_order: &Order, b_order: &Order, volume: Quantity) -> Fill {
+        let mut fill = Fill::new(kind, a_order, b_order, volume);
+        // the contract's price is what settles, even in an auction
+        let contract_price = (kind == MatchKind::Direct)
+            .then(|| self.contract_variant.price(a_order, b_order))
+            .flatten();
+        if let Some(price) = contract_price.or(self.clearing_price) {
+            fill.price = price;
+        }
+        if let Some(schedule) = self.fee_schedule {
+            let (taker, maker) = if a_order.id > b_order.id {
+                (a_order, b_order)
+            } else {
+                (b_order, a_order)
+            };
+            fill.fees = schedule.fees(&fill, taker, maker, self.unit_price);
+        }
+        fill
+    }
+
+    fn is_economic(&self, fill: &Fill) -> bool {
+        match (self.fee_schedule, &fill.fees) {
+            (Some(schedule), Some(fees)) => schedule.is_economic(fees),
+            // fees that overflow cannot be charged
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Matches the orders of two book sides in priority order for as long as
+    /// `crosses` holds for the best remaining pair.
+    fn cross<'a>(
+        &mut self,
+        kind: MatchKind,
+        first: &'a BookSide,
+        second: &'a BookSide,
+        crosses: impl Fn(&Order, &Order) -> bool,
+    ) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        let mut a_side = Cursor::new(first.levels());
+        let mut b_side = Cursor::new(second.levels());
+
+        loop {
+            let (Some(a_order), Some(b_order)) = (a_side.current(self), b_side.current(self))
+            else {
+                // an allocated order left over after its level is done, e.g. when
+                // some of the level was cancelled, is split over what remains
+                if self.allocation.take().is_some() && (a_side.restore() | b_side.restore()) {
+                    continue;
+                }
+                break;
+            };
+            if !crosses(a_order, b_order) {
+                break;
+            }
+
+            // the order with the higher id would take, post-only orders refuse to
+            let a_is_taker = a_order.id > b_order.id;
+            let taker = if a_is_taker { a_order } else { b_order };
+            if taker.post_only && self.clearing_price.is_none() {
+                self.cancelled.insert(taker.id);
+                if a_is_taker {
+                    a_side.advance();
+                } else {
+                    b_side.advance();
+                }
+                continue;
+            }
+
+            if self.is_self_trade(a_order, b_order) {
+                let drop_a = match self.self_trade_prevention {
+                    SelfTradePrevention::CancelOldest => {
+                        self.cancelled.insert(a_order.id.min(b_order.id));
+                        !a_is_taker
+                    }
+                    SelfTradePrevention::CancelNewest => {
+                        self.cancelled.insert(taker.id);
+                        a_is_taker
+                    }
+                    _ => a_is_taker,
+                };
+                if drop_a {
+                    a_side.advance();
+                } else {
+                    b_side.advance();
+                }
+                continue;
+            }
+
+            if a_order.time_in_force == TimeInForce::FillOrKill
+                && !self.approved.contains(&a_order.id)
+            {
+                if self.fills_completely(a_order, b_order, b_side.rest(), |other| {
+                    crosses(a_order, other)
+                }) {
+                    self.approved.insert(a_order.id);
+                } else {
+                    self.cancelled.insert(a_order.id);
+                    a_side.advance();
+                    continue;
+                }
+            }
+            if b_order.time_in_force == TimeInForce::FillOrKill
+                && !self.approved.contains(&b_order.id)
+            {
+                if self.fills_completely(b_order, a_order, a_side.rest(), |other| {
+                    crosses(other, b_order)
+                }) {
+                    self.approved.insert(b_order.id);
+                } else {
+                    self.cancelled.insert(b_order.id);
+                    b_side.advance();
+                    continue;
+                }
+            }
+
+            // the allocation is redone once either order of the pair changes
+            let allocated = self.allocation.as_ref().is_some_and(|(id, shares)| {
+                (*id == a_order.id && shares.contains_key(&b_order.id))
+                    || (*id == b_order.id && shares.contains_key(&a_order.id))
+            });
+            if !allocated {
+                if a_side.restore() | b_side.restore() {
+                    self.allocation = None;
+                    continue;
+                }
+                self.allocation = self.allocate(a_order, b_order, &a_side, &b_side);
+            }
+
+            let mut volume = self.remaining(a_order).min(self.remaining(b_order));
+            // the allocated order's counterparty, whether it is on side a and
+            // its share not yet filled
+            let share = self.allocation.as_ref().map(|(id, shares)| {
+                let counterparty_is_a = *id != a_order.id;
+                let counterparty = if counterparty_is_a { a_order } else { b_order };
+                let share = shares.get(&counterparty.id).copied().unwrap_or_default();
+                (counterparty_is_a, counterparty.id, share)
+            });
+            if let Some((_, _, share)) = share {
+                volume = volume.min(share);
+            }
+            if !volume.is_zero() {
+                let fill = self.fill(kind, a_order, b_order, volume);
+                if let Some((low, _)) = self.bands[fill.outcome as usize].filter(|(low, high)| {
+                    kind == MatchKind::Direct && (fill.price < *low || fill.price > *high)
+                }) {
+                    let drop_a = if fill.price < low {
+                        a_order.price <= b_order.price
+                    } else {
+                        a_order.price >= b_order.price
+                    };
+                    self.held.push(fill);
+                    if drop_a {
+                        a_side.advance();
+                    } else {
+                        b_side.advance();
+                    }
+                    continue;
+                }
+                if !self.is_economic(&fill) {
+                    if self.remaining(a_order) <= self.remaining(b_order) {
+                        a_side.advance();
+                    } else {
+                        b_side.advance();
+                    }
+                    continue;
+                }
+                fills.push(fill);
+                *self.filled.entry(a_order.id).or_default() += volume;
+                *self.filled.entry(b_order.id).or_default() += volume;
+            }
+            // which side's order has no share left in the allocated order
+            let mut pass_a = None;
+            if let (Some((counterparty_is_a, id, share)), Some((_, shares))) =
+                (share, &mut self.allocation)
+            {
+                shares.insert(id, share - volume);
+                if share == volume {
+                    pass_a = Some(counterparty_is_a);
+                }
+            }
+
+            if self.remaining(a_order).is_zero() {
+                a_side.advance();
+            } else if pass_a == Some(true) {
+                a_side.pass();
+            }
+            if self.remaining(b_order).is_zero() {
+                b_side.advance();
+            } else if pass_a == Some(false) {
+                b_side.pass();
+            }
+        }
+        fills
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use alloy::primitives::Address;
+
     use crate::orderbook::{
-        order::{Order, OrderSide},
-        OrderBook,
+        order::{Order, OrderSide, Outcome, TimeInForce},
+        policy::MatchingPolicy,
+        types::{OrderId, Price, Quantity},
+        Fill, MatchKind, MatchingResult, OrderBook, SelfTradePrevention,
     };
 
     #[test]
@@ -168,7 +953,306 @@ mod tests {
         let matches = book.find_matching_orders();
         assert!(matches.is_some());
         let matches = matches.unwrap();
-        assert_eq!(matches.maker_order_id, 1);
-        assert_eq!(matches.taker_order_id, 2);
+        assert_eq!(matches.maker_order_id, OrderId::from(1));
+        assert_eq!(matches.taker_order_id, OrderId::from(2));
+        assert_eq!(matches.expected_price, Some(Price::from(10)));
+        assert_eq!(matches.expected_volume, Some(Quantity::from(100)));
+    }
+
+    #[test]
+    fn test_fifo_within_price_level() {
+        let mut book = OrderBook::new();
+
+        book.add_order(Order::new(3, 1, 10, 10, OrderSide::Sell));
+        book.add_order(Order::new(5, 1, 10, 10, OrderSide::Sell));
+        book.add_order(Order::new(4, 1, 10, 12, OrderSide::Sell));
+        book.add_order(Order::new(6, 1, 10, 10, OrderSide::Buy));
+
+        let matches = book.find_matching_orders().unwrap();
+        assert_eq!(matches.maker_order_id, OrderId::from(3));
+        assert_eq!(matches.taker_order_id, OrderId::from(6));
+    }
+
+    #[test]
+    fn test_remove_and_update() {
+        let mut book = OrderBook::new();
+
+        book.add_order(Order::new(1, 1, 100, 10, OrderSide::Buy));
+        book.add_order(Order::new(2, 1, 100, 12, OrderSide::Sell));
+        assert_eq!(book.len(), 2);
+        assert!(book.find_matching_orders().is_none());
+
+        // a partial fill keeps the order's priority and updates the level volume
+        book.update_order(Order::new(1, 1, 40, 10, OrderSide::Buy));
+        let (price, level) = book
+            .side(Outcome::Yes, OrderSide::Buy)
+            .best_level()
+            .unwrap();
+        assert_eq!(price, Price::from(10));
+        assert_eq!(level.volume(), 40);
+
+        // a zero volume update removes the order
+        book.update_order(Order::new(2, 1, 0, 12, OrderSide::Sell));
+        assert!(book.get_order(2.into()).is_none());
+        assert!(book.side(Outcome::Yes, OrderSide::Sell).is_empty());
+
+        assert!(book.remove_order(1.into()));
+        assert!(!book.remove_order(1.into()));
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_match_until_uncrossed() {
+        let mut book = OrderBook::new();
+
+        book.add_order(Order::new(1, 1, 30, 10, OrderSide::Sell));
+        book.add_order(Order::new(2, 1, 50, 11, OrderSide::Sell));
+        book.add_order(Order::new(3, 1, 50, 13, OrderSide::Sell));
+        book.add_order(Order::new(4, 1, 60, 12, OrderSide::Buy));
+        book.add_order(Order::new(5, 1, 40, 11, OrderSide::Buy));
+
+        let fills = book.find_all_matching_orders();
+        assert_eq!(
+            fills,
+            vec![
+                Fill {
+                    kind: MatchKind::Direct,
+                    taker_order_id: 4.into(),
+                    maker_order_id: 1.into(),
+                    price: 10.into(),
+                    volume: 30.into(),
+                    outcome: Outcome::Yes,
+                    fees: None,
+                },
+                Fill {
+                    kind: MatchKind::Direct,
+                    taker_order_id: 4.into(),
+                    maker_order_id: 2.into(),
+                    price: 11.into(),
+                    volume: 30.into(),
+                    outcome: Outcome::Yes,
+                    fees: None,
+                },
+                Fill {
+                    kind: MatchKind::Direct,
+                    taker_order_id: 5.into(),
+                    maker_order_id: 2.into(),
+                    price: 11.into(),
+                    volume: 20.into(),
+                    outcome: Outcome::Yes,
+                    fees: None,
+                },
+            ]
+        );
+
+        for fill in fills.iter() {
+            book.apply_fill(fill);
+        }
+        assert!(book.find_all_matching_orders().is_empty());
+        assert_eq!(book.get_order(5.into()).unwrap().volume, Quantity::from(20));
+        assert!(book.get_order(2.into()).is_none());
+    }
+
+    #[test]
+    fn test_complementary_matching() {
+        let mut book = OrderBook::with_unit_price(100);
+
+        // Yes bid 60 + No bid 45 pays more than a complete set costs
+        book.add_order(Order::new(1, 1, 10, 60, OrderSide::Buy));
+        book.add_order(Order::new(2, 1, 4, 45, OrderSide::Buy).with_outcome(Outcome::No));
+        // No ask 60 + Yes ask 75 asks more than a complete set is worth
+        book.add_order(Order::new(3, 1, 10, 60, OrderSide::Sell).with_outcome(Outcome::No));
+        book.add_order(Order::new(4, 1, 10, 75, OrderSide::Sell));
+        // the Yes bid first takes the cheaper Yes ask, at the lower price as
+        // the contract does
+        book.add_order(Order::new(5, 1, 5, 55, OrderSide::Sell));
+
+        let fills = book.find_all_matching_orders();
+        assert_eq!(
+            fills,
+            vec![
+                Fill {
+                    kind: MatchKind::Direct,
+                    taker_order_id: 5.into(),
+                    maker_order_id: 1.into(),
+                    price: 55.into(),
+                    volume: 5.into(),
+                    outcome: Outcome::Yes,
+                    fees: None,
+                },
+                Fill {
+                    kind: MatchKind::Mint,
+                    taker_order_id: 2.into(),
+                    maker_order_id: 1.into(),
+                    price: 60.into(),
+                    volume: 4.into(),
+                    outcome: Outcome::Yes,
+                    fees: None,
+                },
+            ]
+        );
+
+        // once the bids are gone, cheaper asks on both outcomes burn a set
+        book.remove_order(1.into());
+        book.remove_order(2.into());
+        book.update_order(Order::new(3, 1, 10, 30, OrderSide::Sell).with_outcome(Outcome::No));
+        book.update_order(Order::new(4, 1, 10, 70, OrderSide::Sell));
+        let burns = book
+            .find_all_matching_orders()
+            .into_iter()
+            .filter(|fill| fill.kind == MatchKind::Burn)
+            .collect::<Vec<_>>();
+        assert_eq!(burns.len(), 2);
+        assert_eq!(
+            (burns[0].taker_order_id, burns[0].volume),
+            (5.into(), 5.into())
+        );
+        assert_eq!(
+            (burns[1].taker_order_id, burns[1].volume),
+            (4.into(), 5.into())
+        );
+        assert!(burns
+            .iter()
+            .all(|fill| fill.maker_order_id == OrderId::from(3)));
+
+        // a book settled on chain only trades Yes directly, the rest of an
+        // immediate-or-cancel No order is cancelled rather than filled
+        let mut book = book.with_on_chain_settlement(true);
+        book.add_order(
+            Order::new(6, 1, 5, 40, OrderSide::Buy)
+                .with_outcome(Outcome::No)
+                .with_time_in_force(TimeInForce::ImmediateOrCancel),
+        );
+        book.add_order(Order::new(7, 1, 5, 70, OrderSide::Buy));
+        let result = book.run_matching();
+        assert!(result
+            .fills
+            .iter()
+            .all(|fill| fill.kind == MatchKind::Direct && fill.maker_order_id != OrderId::from(3)));
+        assert!(result.cancelled.contains(&6.into()));
+    }
+
+    #[test]
+    fn test_time_in_force_and_flags() {
+        let mut book = OrderBook::new();
+        book.set_block_number(10);
+
+        book.add_order(Order::new(1, 1, 5, 10, OrderSide::Sell));
+        // only 5 of 8 can be filled, so the whole order is killed
+        book.add_order(
+            Order::new(2, 1, 8, 10, OrderSide::Buy).with_time_in_force(TimeInForce::FillOrKill),
+        );
+        // fills 5, the remaining 3 are cancelled
+        book.add_order(
+            Order::new(3, 1, 8, 10, OrderSide::Buy)
+                .with_time_in_force(TimeInForce::ImmediateOrCancel),
+        );
+        // would take from the resting buys
+        book.add_order(Order::new(4, 1, 5, 9, OrderSide::Sell).with_post_only(true));
+        // expired before the current block
+        book.add_order(Order::new(5, 1, 1, 20, OrderSide::Buy).with_expiry_block(Some(5)));
+
+        assert_eq!(
+            book.run_matching(),
+            MatchingResult {
+                fills: vec![Fill {
+                    kind: MatchKind::Direct,
+                    taker_order_id: 3.into(),
+                    maker_order_id: 1.into(),
+                    price: 10.into(),
+                    volume: 5.into(),
+                    outcome: Outcome::Yes,
+                    fees: None,
+                }],
+                cancelled: [2, 3, 4, 5].map(OrderId::from).to_vec(),
+                held: Vec::new(),
+            }
+        );
+
+        book.cancel_orders(&[2, 3, 4, 5].map(OrderId::from));
+        assert_eq!(book.len(), 1);
+    }
+
+    #[test]
+    fn test_self_trade_prevention() {
+        let trader = Some(Address::repeat_byte(1));
+        let other = Some(Address::repeat_byte(2));
+        let orders = [
+            Order::new(1, 1, 10, 10, OrderSide::Sell).with_creator(trader),
+            Order::new(2, 1, 10, 11, OrderSide::Sell).with_creator(other),
+            Order::new(3, 1, 10, 12, OrderSide::Buy).with_creator(trader),
+        ];
+        let run = |mode| {
+            let mut book = OrderBook::new().with_self_trade_prevention(mode);
+            for order in orders.iter() {
+                book.add_order(order.clone());
+            }
+            let result = book.run_matching();
+            let fills = result
+                .fills
+                .iter()
+                .map(|fill| (fill.taker_order_id, fill.maker_order_id))
+                .collect::<Vec<_>>();
+            (fills, result.cancelled)
+        };
+        let id = OrderId::from;
+
+        assert_eq!(
+            run(SelfTradePrevention::Allow),
+            (vec![(id(3), id(1))], vec![])
+        );
+        assert_eq!(run(SelfTradePrevention::Skip), (vec![], vec![]));
+        assert_eq!(
+            run(SelfTradePrevention::CancelNewest),
+            (vec![], vec![id(3)])
+        );
+        assert_eq!(
+            run(SelfTradePrevention::CancelOldest),
+            (vec![(id(3), id(2))], vec![id(1)])
+        );
+    }
+
+    #[test]
+    fn test_matching_policies() {
+        let run = |policy: MatchingPolicy| {
+            let mut book = OrderBook::new().with_matching_policy(policy);
+            book.add_order(Order::new(1, 1, 10, 40, OrderSide::Sell));
+            book.add_order(Order::new(2, 1, 30, 40, OrderSide::Sell));
+            book.add_order(Order::new(3, 1, 10, 40, OrderSide::Sell));
+            book.add_order(Order::new(4, 1, 25, 45, OrderSide::Buy));
+            book.add_order(Order::new(5, 1, 10, 41, OrderSide::Buy));
+            book.find_all_matching_orders()
+                .iter()
+                .map(|fill| (fill.taker_order_id, fill.maker_order_id, fill.volume))
+                .collect::<Vec<_>>()
+        };
+        let fills = |fills: &[(u32, u32, u32)]| {
+            fills
+                .iter()
+                .map(|&(taker, maker, volume)| (taker.into(), maker.into(), volume.into()))
+                .collect::<Vec<(OrderId, OrderId, Quantity)>>()
+        };
+
+        assert_eq!(
+            run(MatchingPolicy::Fifo),
+            fills(&[(4, 1, 10), (4, 2, 15), (5, 2, 10)])
+        );
+        assert_eq!(
+            run(MatchingPolicy::SizePriority),
+            fills(&[(4, 2, 25), (5, 2, 5), (5, 1, 5)])
+        );
+        // each buy is split across the whole level, 25 as 5/15/5 and
+        // the next 10 over what is left as 2/6/2
+        assert_eq!(
+            run(MatchingPolicy::ProRata),
+            fills(&[
+                (4, 1, 5),
+                (4, 2, 15),
+                (4, 3, 5),
+                (5, 1, 2),
+                (5, 2, 6),
+                (5, 3, 2)
+            ])
+        );
     }
 }
@@ -1,26 +1,124 @@
-#[derive(Clone, Copy, Debug)]
+use alloy::primitives::Address;
+use serde::{Deserialize, Serialize};
+
+use super::types::{OrderId, Price, Quantity};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
-#[derive(Clone, Debug)]
+/// The outcome token an order trades. One Yes and one No token together
+/// form a complete set, which is worth the unit price.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    #[default]
+    Yes,
+    No,
+}
+
+impl Outcome {
+    pub fn complement(self) -> Self {
+        match self {
+            Outcome::Yes => Outcome::No,
+            Outcome::No => Outcome::Yes,
+        }
+    }
+}
+
+/// How long an order stays in the book.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Rests until it is filled, or until its expiry block if it has one.
+    #[default]
+    #[serde(rename = "gtc")]
+    GoodTillCancel,
+    /// Matches what it can in the next matching pass, the rest is cancelled.
+    #[serde(rename = "ioc")]
+    ImmediateOrCancel,
+    /// Is filled completely in the next matching pass or not at all.
+    #[serde(rename = "fok")]
+    FillOrKill,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Order {
-    pub id: u32,
+    pub id: OrderId,
     pub contract_id: u32,
-    pub volume: u32,
-    pub price: u32,
+    pub volume: Quantity,
+    pub price: Price,
     pub side: OrderSide,
+    pub outcome: Outcome,
+    pub time_in_force: TimeInForce,
+    /// A post-only order is never matched as the taker.
+    pub post_only: bool,
+    /// Last block in which the order can be matched (good-till-block).
+    pub expiry_block: Option<u64>,
+    /// Address that placed the order, if known.
+    pub creator: Option<Address>,
 }
 
 impl Order {
-    pub fn new(id: u32, contract_id: u32, volume: u32, price: u32, side: OrderSide) -> Self {
+    pub fn new(
+        id: impl Into<OrderId>,
+        contract_id: u32,
+        volume: impl Into<Quantity>,
+        price: impl Into<Price>,
+        side: OrderSide,
+    ) -> Self {
         Order {
-            id,
+            id: id.into(),
             contract_id,
-            volume,
-            price,
+            volume: volume.into(),
+            price: price.into(),
             side,
+            outcome: Outcome::Yes,
+            time_in_force: TimeInForce::GoodTillCancel,
+            post_only: false,
+            expiry_block: None,
+            creator: None,
         }
     }
+
+    pub fn with_outcome(mut self, outcome: Outcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn with_post_only(mut self, post_only: bool) -> Self {
+        self.post_only = post_only;
+        self
+    }
+
+    pub fn with_expiry_block(mut self, expiry_block: Option<u64>) -> Self {
+        self.expiry_block = expiry_block;
+        self
+    }
+
+    pub fn with_creator(mut self, creator: Option<Address>) -> Self {
+        self.creator = creator;
+        self
+    }
+
+    /// Immediate orders only take part in a single matching pass.
+    pub fn is_immediate(&self) -> bool {
+        self.time_in_force != TimeInForce::GoodTillCancel
+    }
+
+    pub fn is_expired(&self, block_number: u64) -> bool {
+        self.expiry_block
+            .is_some_and(|expiry_block| expiry_block < block_number)
+    }
+
+    /// Whether both orders are known to come from the same address.
+    pub fn has_same_creator(&self, other: &Order) -> bool {
+        self.creator.is_some() && self.creator == other.creator
+    }
 }
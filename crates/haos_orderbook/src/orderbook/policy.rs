@@ -0,0 +1,216 @@
+use std::{collections::btree_map, str::FromStr, vec};
+
+use super::{
+    level::PriceLevel,
+    order::{Order, TimeInForce},
+    types::{OrderId, Quantity},
+};
+
+/// How orders at the same price are prioritised and how a counterparty's
+/// volume is allocated among them. Prices always have priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchingPolicy {
+    /// Oldest order first.
+    #[default]
+    Fifo,
+    /// The volume of a crossing order is split across the price level in
+    /// proportion to each order's remaining volume. Simulation-only, neither
+    /// contract can settle a pro-rata share of an order.
+    ProRata,
+    /// Largest remaining order first, then oldest first.
+    SizePriority,
+}
+
+/// Orders of a price level in matching order.
+#[derive(Clone, Debug)]
+pub enum LevelQueue<'a> {
+    /// Time priority, read from the level as the orders are needed.
+    Time(btree_map::Values<'a, OrderId, Order>),
+    Sorted(vec::IntoIter<&'a Order>),
+}
+
+impl<'a> Iterator for LevelQueue<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            LevelQueue::Time(orders) => orders.next(),
+            LevelQueue::Sorted(orders) => orders.next(),
+        }
+    }
+}
+
+impl FromStr for MatchingPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(MatchingPolicy::Fifo),
+            "pro_rata" => Ok(MatchingPolicy::ProRata),
+            "size_priority" => Ok(MatchingPolicy::SizePriority),
+            _ => Err(anyhow::anyhow!("Unknown matching policy: {}", s)),
+        }
+    }
+}
+
+impl MatchingPolicy {
+    /// Orders of a price level in the order they are matched.
+    pub fn queue<'a>(
+        &self,
+        level: &'a PriceLevel,
+        remaining: impl Fn(&Order) -> Quantity,
+    ) -> LevelQueue<'a> {
+        match self {
+            MatchingPolicy::Fifo | MatchingPolicy::ProRata => LevelQueue::Time(level.iter()),
+            MatchingPolicy::SizePriority => {
+                let mut orders = level.iter().collect::<Vec<_>>();
+                // the sort is stable, so equal sizes stay in time priority
+                orders.sort_by_key(|order| std::cmp::Reverse(remaining(order)));
+                LevelQueue::Sorted(orders.into_iter())
+            }
+        }
+    }
+
+    /// Splits `volume` across the orders of a level, given with their
+    /// remaining volume in queue order, and returns each order's id and
+    /// share. Returns `None` if the orders are simply filled in queue order.
+    ///
+    /// Pro-rata shares are rounded down and the units left over go to the
+    /// orders at the front of the queue. Fill-or-kill orders cannot take a
+    /// partial share, so they are allocated in full first.
+    pub fn allocate<'a>(
+        &self,
+        volume: Quantity,
+        orders: impl IntoIterator<Item = (&'a Order, Quantity)>,
+    ) -> Option<Vec<(OrderId, Quantity)>> {
+        if *self != MatchingPolicy::ProRata {
+            return None;
+        }
+        let orders = orders
+            .into_iter()
+            .map(|(order, remaining)| (order, remaining.get()))
+            .collect::<Vec<_>>();
+        let mut shares = vec![0; orders.len()];
+        let mut volume = u64::from(volume);
+        for (share, (order, remaining)) in shares.iter_mut().zip(&orders) {
+            if order.time_in_force == TimeInForce::FillOrKill && u64::from(*remaining) <= volume {
+                *share = *remaining;
+                volume -= u64::from(*remaining);
+            }
+        }
+
+        let total = orders
+            .iter()
+            .zip(&shares)
+            .filter(|((order, _), _)| order.time_in_force != TimeInForce::FillOrKill)
+            .map(|((_, remaining), _)| u64::from(*remaining))
+            .sum::<u64>();
+        if total <= volume {
+            for (share, (order, remaining)) in shares.iter_mut().zip(&orders) {
+                if order.time_in_force != TimeInForce::FillOrKill {
+                    *share = *remaining;
+                }
+            }
+            return Some(Self::with_ids(&orders, shares));
+        }
+
+        let mut left = volume;
+        for (share, (order, remaining)) in shares.iter_mut().zip(&orders) {
+            if *share == 0 && order.time_in_force != TimeInForce::FillOrKill {
+                // below the remaining volume, as volume < total
+                *share = (volume * u64::from(*remaining) / total) as u32;
+                left -= u64::from(*share);
+            }
+        }
+        for (share, (order, remaining)) in shares.iter_mut().zip(&orders) {
+            if left == 0 {
+                break;
+            }
+            if *share < *remaining && order.time_in_force != TimeInForce::FillOrKill {
+                *share += 1;
+                left -= 1;
+            }
+        }
+        Some(Self::with_ids(&orders, shares))
+    }
+
+    fn with_ids(orders: &[(&Order, u32)], shares: Vec<u32>) -> Vec<(OrderId, Quantity)> {
+        orders
+            .iter()
+            .map(|(order, _)| order.id)
+            .zip(shares.into_iter().map(Quantity::from))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::orderbook::{
+        level::PriceLevel,
+        order::{Order, OrderSide, TimeInForce},
+        policy::MatchingPolicy,
+        types::{OrderId, Quantity},
+    };
+
+    #[test]
+    fn test_queue_and_allocate() {
+        let mut level = PriceLevel::default();
+        level.insert(Order::new(1, 0, 10, 40, OrderSide::Sell));
+        level.insert(Order::new(2, 0, 30, 40, OrderSide::Sell));
+        level.insert(Order::new(3, 0, 10, 40, OrderSide::Sell));
+
+        let ids = |policy: MatchingPolicy| {
+            policy
+                .queue(&level, |order| order.volume)
+                .map(|order| order.id)
+                .collect::<Vec<_>>()
+        };
+        let expected = |ids: [u32; 3]| ids.map(OrderId::from).to_vec();
+        assert_eq!(ids(MatchingPolicy::Fifo), expected([1, 2, 3]));
+        assert_eq!(ids(MatchingPolicy::ProRata), expected([1, 2, 3]));
+        assert_eq!(ids(MatchingPolicy::SizePriority), expected([2, 1, 3]));
+
+        let orders = level
+            .iter()
+            .map(|order| (order, order.volume))
+            .collect::<Vec<_>>();
+        let shares = |policy: MatchingPolicy, volume: u32, orders: &[(&Order, Quantity)]| {
+            policy
+                .allocate(volume.into(), orders.iter().copied())
+                .map(|shares| {
+                    shares
+                        .into_iter()
+                        .map(|(_, share)| share.get())
+                        .collect::<Vec<_>>()
+                })
+        };
+        assert_eq!(shares(MatchingPolicy::Fifo, 25, &orders), None);
+        assert_eq!(
+            shares(MatchingPolicy::ProRata, 25, &orders),
+            Some(vec![5, 15, 5])
+        );
+        // 2 + 6 + 2 rounded down, the unit left over goes to the oldest order
+        assert_eq!(
+            shares(MatchingPolicy::ProRata, 11, &orders),
+            Some(vec![3, 6, 2])
+        );
+        assert_eq!(
+            shares(MatchingPolicy::ProRata, 60, &orders),
+            Some(vec![10, 30, 10])
+        );
+
+        // a fill-or-kill order is allocated in full or not at all
+        let large = Order::new(4, 0, 30, 40, OrderSide::Sell);
+        let fok =
+            Order::new(5, 0, 5, 40, OrderSide::Sell).with_time_in_force(TimeInForce::FillOrKill);
+        let orders = vec![(&large, large.volume), (&fok, fok.volume)];
+        assert_eq!(
+            shares(MatchingPolicy::ProRata, 10, &orders),
+            Some(vec![5, 5])
+        );
+        assert_eq!(
+            shares(MatchingPolicy::ProRata, 4, &orders),
+            Some(vec![4, 0])
+        );
+    }
+}
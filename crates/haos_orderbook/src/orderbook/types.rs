@@ -0,0 +1,207 @@
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+use alloy::primitives::U256;
+use serde::{Deserialize, Serialize};
+
+/// Id of an order in its OrderBook contract, as wide as the on-chain `uint256`.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct OrderId(U256);
+
+impl OrderId {
+    pub const ZERO: OrderId = OrderId(U256::ZERO);
+
+    pub fn get(&self) -> U256 {
+        self.0
+    }
+}
+
+impl From<U256> for OrderId {
+    fn from(id: U256) -> Self {
+        OrderId(id)
+    }
+}
+
+impl From<u32> for OrderId {
+    fn from(id: u32) -> Self {
+        OrderId(U256::from(id))
+    }
+}
+
+impl fmt::Display for OrderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A value that does not fit the contract's 32-bit order fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfRange {
+    pub field: &'static str,
+    pub value: u64,
+}
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} does not fit in 32 bits", self.field, self.value)
+    }
+}
+
+impl std::error::Error for OutOfRange {}
+
+// a `euint32` order field with the conversions and arithmetic it needs
+macro_rules! contract_u32 {
+    ($(#[$doc:meta])* $name:ident, $field:literal) => {
+        $(#[$doc])*
+        #[derive(
+            Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(u32);
+
+        impl $name {
+            pub const ZERO: $name = $name(0);
+
+            pub fn get(&self) -> u32 {
+                self.0
+            }
+
+            pub fn is_zero(&self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl From<u32> for $name {
+            fn from(value: u32) -> Self {
+                $name(value)
+            }
+        }
+
+        impl TryFrom<u64> for $name {
+            type Error = OutOfRange;
+
+            fn try_from(value: u64) -> Result<Self, Self::Error> {
+                u32::try_from(value).map($name).map_err(|_| OutOfRange {
+                    field: $field,
+                    value,
+                })
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(value: $name) -> Self {
+                u64::from(value.0)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+contract_u32!(
+    /// Amount of outcome tokens an order trades.
+    Quantity,
+    "amount"
+);
+contract_u32!(
+    /// Price of one outcome token, in units of the unit price.
+    Price,
+    "price"
+);
+
+impl Quantity {
+    pub fn saturating_sub(self, other: Quantity) -> Quantity {
+        Quantity(self.0.saturating_sub(other.0))
+    }
+
+    /// `amount * price` as `placeOrder` computes it, `None` if it wraps.
+    pub fn checked_notional(self, price: Price) -> Option<u32> {
+        self.0.checked_mul(price.0)
+    }
+}
+
+impl Add for Quantity {
+    type Output = Quantity;
+
+    fn add(self, other: Quantity) -> Quantity {
+        Quantity(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Quantity {
+    fn add_assign(&mut self, other: Quantity) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Quantity {
+    type Output = Quantity;
+
+    fn sub(self, other: Quantity) -> Quantity {
+        Quantity(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Quantity {
+    fn sub_assign(&mut self, other: Quantity) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Quantity {
+    fn sum<I: Iterator<Item = Quantity>>(iter: I) -> Quantity {
+        iter.fold(Quantity::ZERO, Add::add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U256;
+
+    use crate::orderbook::types::{OrderId, OutOfRange, Price, Quantity};
+
+    #[test]
+    fn test_conversions() {
+        let id = OrderId::from(U256::MAX);
+        assert!(id > OrderId::from(u32::MAX));
+        assert_eq!(id.get(), U256::MAX);
+        assert_eq!(
+            serde_json::to_string(&OrderId::from(255)).unwrap(),
+            "\"0xff\""
+        );
+
+        assert_eq!(Quantity::try_from(7_u64), Ok(Quantity::from(7)));
+        assert_eq!(
+            Price::try_from(u64::from(u32::MAX) + 1),
+            Err(OutOfRange {
+                field: "price",
+                value: 1 << 32
+            })
+        );
+        assert_eq!(serde_json::to_string(&Price::from(40)).unwrap(), "40");
+
+        let quantity = Quantity::from(10) - Quantity::from(4);
+        assert_eq!(quantity.saturating_sub(Quantity::from(7)), Quantity::ZERO);
+        assert_eq!(quantity.checked_notional(Price::from(3)), Some(18));
+        assert_eq!(
+            Quantity::from(u32::MAX).checked_notional(Price::from(2)),
+            None
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{constants::MAX_TRADE_HISTORY, orderbook::order::Outcome};
+
+/// A confirmed match between two orders.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    pub block_number: u64,
+    /// Unix time in seconds of the block the match was logged in.
+    pub timestamp: u64,
+    /// Outcome token that changed hands, `price` is its price.
+    #[serde(default)]
+    pub outcome: Outcome,
+    pub price: u32,
+    pub volume: u32,
+}
+
+/// Width of a candle, in blocks or in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    Blocks(u64),
+    Seconds(u64),
+}
+
+/// Start of a range of trades, inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Since {
+    Block(u64),
+    Timestamp(u64),
+}
+
+/// Open, high, low and close prices and traded volume over a range of trades.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Candle {
+    /// First block or timestamp covered by the candle.
+    pub start: u64,
+    pub open: u32,
+    pub high: u32,
+    pub low: u32,
+    pub close: u32,
+    pub volume: u64,
+    /// Sum of price * volume, used for the VWAP.
+    pub notional: u64,
+    pub trades: usize,
+}
+
+impl Candle {
+    fn new(start: u64, trade: &Trade) -> Self {
+        let mut candle = Candle {
+            start,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: 0,
+            notional: 0,
+            trades: 0,
+        };
+        candle.add(trade);
+        candle
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += u64::from(trade.volume);
+        self.notional += u64::from(trade.price) * u64::from(trade.volume);
+        self.trades += 1;
+    }
+
+    /// Volume weighted average price.
+    pub fn vwap(&self) -> Option<f64> {
+        (self.volume > 0).then(|| self.notional as f64 / self.volume as f64)
+    }
+}
+
+/// Price discovery of one market, with a price series per outcome: the
+/// price of the last match of an outcome is its market price, and the Yes
+/// price divided by the unit price is the implied probability of the event.
+#[derive(Clone, Debug)]
+pub struct PriceDiscovery {
+    unit_price: u32,
+    // confirmed trades of the Yes and of the No outcome in the order they
+    // were matched, oldest first
+    trades: [VecDeque<Trade>; 2],
+}
+
+impl PriceDiscovery {
+    pub fn new(unit_price: u32) -> Self {
+        PriceDiscovery {
+            unit_price,
+            trades: [VecDeque::new(), VecDeque::new()],
+        }
+    }
+
+    fn series(&self, outcome: Outcome) -> &VecDeque<Trade> {
+        &self.trades[outcome as usize]
+    }
+
+    pub fn record(&mut self, trade: Trade) {
+        let trades = &mut self.trades[trade.outcome as usize];
+        if trades.len() == MAX_TRADE_HISTORY {
+            trades.pop_front();
+        }
+        trades.push_back(trade);
+    }
+
+    /// Forgets the trades after `block_number`, which a reorg orphaned.
+    pub fn rollback(&mut self, block_number: u64) {
+        for trades in self.trades.iter_mut() {
+            while trades
+                .back()
+                .is_some_and(|trade| trade.block_number > block_number)
+            {
+                trades.pop_back();
+            }
+        }
+    }
+
+    /// Trades of both outcomes, the Yes series first.
+    pub fn trades(&self) -> impl Iterator<Item = &Trade> + '_ {
+        self.trades.iter().flatten()
+    }
+
+    pub fn last_trade(&self, outcome: Outcome) -> Option<&Trade> {
+        self.series(outcome).back()
+    }
+
+    pub fn last_price(&self, outcome: Outcome) -> Option<u32> {
+        self.last_trade(outcome).map(|trade| trade.price)
+    }
+
+    /// Price of the Yes outcome implied by the latest trade of either
+    /// outcome, a No trade at a price implies the unit price minus it.
+    pub fn yes_price(&self) -> Option<u32> {
+        let yes = self.last_trade(Outcome::Yes);
+        let no = self.last_trade(Outcome::No);
+        match (yes, no) {
+            (Some(yes), Some(no)) if no.block_number > yes.block_number => {
+                Some(self.unit_price.saturating_sub(no.price))
+            }
+            (Some(yes), _) => Some(yes.price),
+            (None, Some(no)) => Some(self.unit_price.saturating_sub(no.price)),
+            (None, None) => None,
+        }
+    }
+
+    /// Probability of the Yes outcome implied by the last price, from 0 to 1.
+    pub fn implied_probability(&self) -> Option<f64> {
+        self.yes_price()
+            .map(|price| f64::from(price) / f64::from(self.unit_price))
+    }
+
+    /// Summary of the trades of `outcome` since the given block or time.
+    pub fn summary(&self, outcome: Outcome, since: Since) -> Option<Candle> {
+        let mut trades = self.series(outcome).iter().filter(|trade| match since {
+            Since::Block(block_number) => trade.block_number >= block_number,
+            Since::Timestamp(timestamp) => trade.timestamp >= timestamp,
+        });
+        let first = trades.next()?;
+        let start = match since {
+            Since::Block(_) => first.block_number,
+            Since::Timestamp(_) => first.timestamp,
+        };
+        let mut candle = Candle::new(start, first);
+        trades.for_each(|trade| candle.add(trade));
+        Some(candle)
+    }
+
+    pub fn vwap(&self, outcome: Outcome, since: Since) -> Option<f64> {
+        self.summary(outcome, since)?.vwap()
+    }
+
+    pub fn volume(&self, outcome: Outcome, since: Since) -> u64 {
+        self.summary(outcome, since)
+            .map_or(0, |candle| candle.volume)
+    }
+
+    /// OHLC candles of the trades of `outcome` of the given width, oldest
+    /// first. Windows without trades are left out.
+    pub fn candles(&self, outcome: Outcome, window: Window) -> Vec<Candle> {
+        let mut candles: Vec<Candle> = Vec::new();
+        for trade in self.series(outcome).iter() {
+            let start = match window {
+                Window::Blocks(blocks) => trade.block_number - trade.block_number % blocks.max(1),
+                Window::Seconds(seconds) => trade.timestamp - trade.timestamp % seconds.max(1),
+            };
+            match candles.last_mut() {
+                Some(candle) if candle.start == start => candle.add(trade),
+                _ => candles.push(Candle::new(start, trade)),
+            }
+        }
+        candles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        orderbook::order::Outcome,
+        prices::{PriceDiscovery, Since, Trade, Window},
+    };
+
+    fn trade(block_number: u64, timestamp: u64, price: u32, volume: u32) -> Trade {
+        Trade {
+            block_number,
+            timestamp,
+            outcome: Outcome::Yes,
+            price,
+            volume,
+        }
+    }
+
+    #[test]
+    fn test_price_discovery() {
+        let mut prices = PriceDiscovery::new(100);
+        assert_eq!(prices.last_price(Outcome::Yes), None);
+        assert_eq!(prices.vwap(Outcome::Yes, Since::Block(0)), None);
+
+        prices.record(trade(10, 1_000, 40, 10));
+        prices.record(trade(11, 1_030, 50, 30));
+        prices.record(trade(25, 1_065, 45, 20));
+
+        assert_eq!(prices.last_price(Outcome::Yes), Some(45));
+        assert_eq!(prices.implied_probability(), Some(0.45));
+        assert_eq!(prices.volume(Outcome::Yes, Since::Block(11)), 50);
+        assert_eq!(prices.vwap(Outcome::Yes, Since::Block(11)), Some(48.0));
+        assert_eq!(
+            prices.vwap(Outcome::Yes, Since::Timestamp(0)),
+            Some(2_800.0 / 60.0)
+        );
+
+        let candles = prices.candles(Outcome::Yes, Window::Blocks(10));
+        assert_eq!(candles.len(), 2);
+        assert_eq!(candles[0].start, 10);
+        assert_eq!(
+            (
+                candles[0].open,
+                candles[0].high,
+                candles[0].low,
+                candles[0].close
+            ),
+            (40, 50, 40, 50)
+        );
+        assert_eq!(candles[1].start, 20);
+        assert_eq!(candles[1].volume, 20);
+
+        let candles = prices.candles(Outcome::Yes, Window::Seconds(60));
+        assert_eq!(
+            candles.iter().map(|c| c.start).collect::<Vec<_>>(),
+            vec![960, 1_020]
+        );
+        assert_eq!(candles[1].trades, 2);
+
+        // a reorg orphaned the blocks after 11
+        prices.rollback(11);
+        assert_eq!(prices.last_price(Outcome::Yes), Some(50));
+        assert_eq!(prices.trades().count(), 2);
+
+        // No trades are a series of their own, the latest trade of either
+        // outcome sets the implied probability
+        prices.record(Trade {
+            outcome: Outcome::No,
+            ..trade(12, 1_040, 70, 5)
+        });
+        assert_eq!(prices.last_price(Outcome::Yes), Some(50));
+        assert_eq!(prices.last_price(Outcome::No), Some(70));
+        assert_eq!(prices.volume(Outcome::Yes, Since::Block(12)), 0);
+        assert_eq!(prices.implied_probability(), Some(0.3));
+        prices.record(trade(13, 1_050, 35, 5));
+        assert_eq!(prices.implied_probability(), Some(0.35));
+        assert_eq!(prices.candles(Outcome::No, Window::Blocks(10)).len(), 1);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alloy::primitives::Address;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    chain::LogPosition,
+    orderbook::{lifecycle::MarketState, order::Order, types::OrderId, Fill},
+    prices::Trade,
+};
+
+/// Version of the snapshot format, snapshots of other versions are ignored.
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXTENSION: &str = "json";
+
+/// State of a single market at the snapshot block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    pub contract_id: u32,
+    pub address: Address,
+    pub block_number: u64,
+    #[serde(default)]
+    pub state: MarketState,
+    /// Block a market halted by its circuit breaker resumes at.
+    #[serde(default)]
+    pub resume_block: Option<u64>,
+    pub orders: Vec<Order>,
+    pub trades: Vec<Trade>,
+}
+
+/// Matcher state after every event up to and including `block_number`
+/// has been handled.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub block_number: u64,
+    pub markets: Vec<MarketSnapshot>,
+    /// Order updates not yet applied, as (market address, order id, log position)
+    pub waiting_orders: Vec<(Address, OrderId, LogPosition)>,
+    /// Settled fills awaiting confirmation, as (contract id, fill)
+    pub pending_fills: Vec<(u32, Fill)>,
+}
+
+/// Directory of snapshot files, one per snapshot block.
+#[derive(Clone, Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    // number of snapshot files kept on disk
+    keep: usize,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            keep: 3,
+        }
+    }
+
+    pub fn with_keep(mut self, keep: usize) -> Self {
+        self.keep = keep.max(1);
+        self
+    }
+
+    /// Writes a snapshot and prunes old ones. The file is written under a
+    /// temporary name first so that a crash never leaves a partial snapshot.
+    pub fn save(&self, snapshot: &Snapshot) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(snapshot.block_number);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(snapshot)?)?;
+        fs::rename(&tmp_path, &path)?;
+        info!(
+            "Saved snapshot at block {} to {:?}",
+            snapshot.block_number, path
+        );
+
+        for (_, stale) in self.list()?.into_iter().skip(self.keep) {
+            fs::remove_file(stale)?;
+        }
+        Ok(path)
+    }
+
+    /// Loads the newest snapshot that can be read and has the current version.
+    pub fn load_latest(&self) -> Result<Option<Snapshot>> {
+        self.load_latest_until(u64::MAX)
+    }
+
+    /// Loads the newest usable snapshot taken at or before `block_number`.
+    pub fn load_latest_until(&self, block_number: u64) -> Result<Option<Snapshot>> {
+        for (_, path) in self
+            .list()?
+            .into_iter()
+            .filter(|(snapshot_block, _)| *snapshot_block <= block_number)
+        {
+            match Self::read(&path) {
+                Ok(snapshot) if snapshot.version == SNAPSHOT_VERSION => return Ok(Some(snapshot)),
+                Ok(snapshot) => warn!(
+                    "Ignoring snapshot {:?} with version {}",
+                    path, snapshot.version
+                ),
+                Err(e) => warn!("Ignoring unreadable snapshot {:?}: {}", path, e),
+            }
+        }
+        Ok(None)
+    }
+
+    fn read(path: &Path) -> Result<Snapshot> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    fn path(&self, block_number: u64) -> PathBuf {
+        self.dir.join(format!(
+            "{}{:020}.{}",
+            SNAPSHOT_PREFIX, block_number, SNAPSHOT_EXTENSION
+        ))
+    }
+
+    // snapshot files with their block number, newest first
+    fn list(&self) -> Result<Vec<(u64, PathBuf)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SNAPSHOT_EXTENSION) {
+                continue;
+            }
+            let block_number = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix(SNAPSHOT_PREFIX))
+                .and_then(|block| block.parse::<u64>().ok());
+            if let Some(block_number) = block_number {
+                snapshots.push((block_number, path));
+            }
+        }
+        snapshots.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use alloy::primitives::Address;
+
+    use crate::{
+        chain::LogPosition,
+        orderbook::{
+            lifecycle::MarketState,
+            order::{Order, OrderSide},
+        },
+        snapshot::{MarketSnapshot, Snapshot, SnapshotStore, SNAPSHOT_VERSION},
+    };
+
+    fn snapshot(block_number: u64) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            block_number,
+            markets: vec![MarketSnapshot {
+                contract_id: 0,
+                address: Address::repeat_byte(1),
+                block_number,
+                state: MarketState::Halted,
+                resume_block: Some(block_number + 10),
+                orders: vec![Order::new(1, 0, 10, 40, OrderSide::Buy)],
+                trades: Vec::new(),
+            }],
+            waiting_orders: vec![(
+                Address::repeat_byte(1),
+                2.into(),
+                LogPosition::new(block_number, 0),
+            )],
+            pending_fills: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_load_latest_valid_snapshot() {
+        let dir = env::temp_dir().join(format!("haos-snapshots-{}", std::process::id()));
+        let store = SnapshotStore::new(&dir).with_keep(2);
+        assert_eq!(store.load_latest().unwrap(), None);
+
+        store.save(&snapshot(10)).unwrap();
+        store.save(&snapshot(20)).unwrap();
+        store.save(&snapshot(30)).unwrap();
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        // a corrupt newer snapshot is skipped
+        fs::write(store.path(40), b"{").unwrap();
+        assert_eq!(store.load_latest().unwrap(), Some(snapshot(30)));
+        assert_eq!(store.load_latest_until(29).unwrap(), Some(snapshot(20)));
+        assert_eq!(store.load_latest_until(19).unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
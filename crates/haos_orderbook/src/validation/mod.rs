@@ -0,0 +1,288 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chain::LogPosition,
+    constants::{MAX_ORDER_VOLUME, UNIT_PRICE},
+    orderbook::{
+        order::Order,
+        types::{OrderId, OutOfRange},
+    },
+};
+
+/// Why a decrypted order was kept out of the book.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Rejection {
+    /// `placeOrder` locks `amount * price` as a `euint32`, which wraps.
+    NotionalOverflow {
+        volume: u32,
+        price: u32,
+    },
+    ZeroPrice,
+    PriceOutOfRange {
+        price: u32,
+        min: u32,
+        max: u32,
+    },
+    OffTick {
+        price: u32,
+        tick_size: u32,
+    },
+    OffLot {
+        volume: u32,
+        lot_size: u32,
+    },
+    VolumeTooLarge {
+        volume: u32,
+        max: u32,
+    },
+    /// The decrypted order has a field the contract could not have stored.
+    OutOfRange {
+        field: String,
+        value: u64,
+    },
+}
+
+impl From<&OutOfRange> for Rejection {
+    fn from(error: &OutOfRange) -> Self {
+        Rejection::OutOfRange {
+            field: error.field.to_string(),
+            value: error.value,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::NotionalOverflow { volume, price } => write!(
+                f,
+                "volume {} at price {} overflows 32-bit contract arithmetic",
+                volume, price
+            ),
+            Rejection::ZeroPrice => write!(f, "price is zero"),
+            Rejection::PriceOutOfRange { price, min, max } => {
+                write!(f, "price {} is outside {}..={}", price, min, max)
+            }
+            Rejection::OffTick { price, tick_size } => {
+                write!(f, "price {} is not a multiple of tick {}", price, tick_size)
+            }
+            Rejection::OffLot { volume, lot_size } => {
+                write!(f, "volume {} is not a multiple of lot {}", volume, lot_size)
+            }
+            Rejection::VolumeTooLarge { volume, max } => {
+                write!(f, "volume {} is above the maximum of {}", volume, max)
+            }
+            Rejection::OutOfRange { field, value } => {
+                write!(f, "{} {} does not fit in 32 bits", field, value)
+            }
+        }
+    }
+}
+
+/// Limits a market puts on the orders it matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrderRules {
+    pub tick_size: u32,
+    pub lot_size: u32,
+    pub min_price: u32,
+    /// Prices are probabilities in units of the unit price by default.
+    pub max_price: u32,
+    pub max_volume: Option<u32>,
+}
+
+impl Default for OrderRules {
+    fn default() -> Self {
+        OrderRules {
+            tick_size: 1,
+            lot_size: 1,
+            min_price: 1,
+            max_price: UNIT_PRICE,
+            max_volume: Some(MAX_ORDER_VOLUME),
+        }
+    }
+}
+
+impl OrderRules {
+    /// Every rule the order breaks. Orders without volume are filled or
+    /// cancelled and are always accepted, so that they leave the book.
+    pub fn check(&self, order: &Order) -> Vec<Rejection> {
+        let mut rejections = Vec::new();
+        if order.volume.is_zero() {
+            return rejections;
+        }
+        let (volume, price) = (order.volume.get(), order.price.get());
+        if order.volume.checked_notional(order.price).is_none() {
+            rejections.push(Rejection::NotionalOverflow { volume, price });
+        }
+        if price == 0 {
+            rejections.push(Rejection::ZeroPrice);
+        } else if price < self.min_price || price > self.max_price {
+            rejections.push(Rejection::PriceOutOfRange {
+                price,
+                min: self.min_price,
+                max: self.max_price,
+            });
+        }
+        if self.tick_size > 1 && price % self.tick_size != 0 {
+            rejections.push(Rejection::OffTick {
+                price,
+                tick_size: self.tick_size,
+            });
+        }
+        if self.lot_size > 1 && volume % self.lot_size != 0 {
+            rejections.push(Rejection::OffLot {
+                volume,
+                lot_size: self.lot_size,
+            });
+        }
+        if let Some(max) = self.max_volume.filter(|max| volume > *max) {
+            rejections.push(Rejection::VolumeTooLarge { volume, max });
+        }
+        rejections
+    }
+}
+
+/// An order kept out of the book, with the reasons it was rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedOrder {
+    pub contract_id: u32,
+    pub id: OrderId,
+    /// The decrypted order, unless it could not be read into one.
+    pub order: Option<Order>,
+    pub rejections: Vec<Rejection>,
+    /// Position of the update that was rejected.
+    pub position: LogPosition,
+}
+
+/// Rejected orders of all markets, keyed by (contract id, order id). An
+/// order stays quarantined until an update of it is accepted.
+#[derive(Clone, Debug, Default)]
+pub struct Quarantine {
+    orders: BTreeMap<(u32, OrderId), QuarantinedOrder>,
+}
+
+impl Quarantine {
+    pub fn insert(&mut self, order: QuarantinedOrder) {
+        self.orders.insert((order.contract_id, order.id), order);
+    }
+
+    pub fn release(&mut self, contract_id: u32, id: OrderId) -> Option<QuarantinedOrder> {
+        self.orders.remove(&(contract_id, id))
+    }
+
+    pub fn get(&self, contract_id: u32, id: OrderId) -> Option<&QuarantinedOrder> {
+        self.orders.get(&(contract_id, id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &QuarantinedOrder> + '_ {
+        self.orders.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chain::LogPosition,
+        orderbook::{
+            order::{Order, OrderSide},
+            types::{OutOfRange, Price},
+        },
+        validation::{OrderRules, Quarantine, QuarantinedOrder, Rejection},
+    };
+
+    #[test]
+    fn test_rules_and_quarantine() {
+        let rules = OrderRules::default();
+        assert!(rules
+            .check(&Order::new(1, 0, 10, 40, OrderSide::Buy))
+            .is_empty());
+        // filled orders always pass
+        assert!(rules
+            .check(&Order::new(1, 0, 0, 0, OrderSide::Buy))
+            .is_empty());
+        assert_eq!(
+            rules.check(&Order::new(1, 0, 10, 0, OrderSide::Buy)),
+            vec![Rejection::ZeroPrice]
+        );
+        assert_eq!(
+            rules.check(&Order::new(1, 0, u32::MAX / 2, 150, OrderSide::Sell)),
+            vec![
+                Rejection::NotionalOverflow {
+                    volume: u32::MAX / 2,
+                    price: 150
+                },
+                Rejection::PriceOutOfRange {
+                    price: 150,
+                    min: 1,
+                    max: 100
+                },
+                Rejection::VolumeTooLarge {
+                    volume: u32::MAX / 2,
+                    max: 100
+                },
+            ]
+        );
+
+        let rules = OrderRules {
+            tick_size: 5,
+            lot_size: 10,
+            ..OrderRules::default()
+        };
+        let rejections = rules.check(&Order::new(1, 0, 105, 42, OrderSide::Buy));
+        assert_eq!(
+            rejections
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "price 42 is not a multiple of tick 5",
+                "volume 105 is not a multiple of lot 10",
+                "volume 105 is above the maximum of 100",
+            ]
+        );
+
+        let mut quarantine = Quarantine::default();
+        quarantine.insert(QuarantinedOrder {
+            contract_id: 3,
+            id: 1.into(),
+            order: Some(Order::new(1, 3, 105, 42, OrderSide::Buy)),
+            rejections,
+            position: LogPosition::new(7, 1),
+        });
+        assert_eq!(quarantine.get(3, 1.into()).unwrap().rejections.len(), 3);
+        assert!(quarantine.get(0, 1.into()).is_none());
+        assert!(quarantine.release(3, 1.into()).is_some());
+        assert!(quarantine.is_empty());
+
+        // an order that cannot be read is quarantined without one
+        let error = Price::try_from(1u64 << 40).unwrap_err();
+        let rejection = Rejection::from(&error);
+        assert_eq!(
+            rejection.to_string(),
+            OutOfRange {
+                field: error.field,
+                value: 1 << 40
+            }
+            .to_string()
+        );
+        quarantine.insert(QuarantinedOrder {
+            contract_id: 0,
+            id: 2.into(),
+            order: None,
+            rejections: vec![rejection],
+            position: LogPosition::new(8, 0),
+        });
+        assert!(quarantine.get(0, 2.into()).unwrap().order.is_none());
+    }
+}
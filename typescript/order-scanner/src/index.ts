@@ -1,3 +1,4 @@
+import { isAddress } from "viem";
 import { getOrderById } from "./order";
 
 const server = Bun.serve({
@@ -16,7 +17,16 @@ const server = Bun.serve({
           });
         }
 
-        const order = await getOrderById(BigInt(orderId));
+        // Markets are told apart by their contract, the configured one is the default
+        const contract = url.searchParams.get("contract");
+        if (contract !== null && !isAddress(contract)) {
+          return new Response(JSON.stringify({ error: "Invalid contract address" }), {
+            status: 400,
+            headers: { "Content-Type": "application/json" },
+          });
+        }
+
+        const order = await getOrderById(BigInt(orderId), contract ?? undefined);
         return new Response(JSON.stringify(order), {
           status: 200,
           headers: { "Content-Type": "application/json" },
@@ -1,3 +1,5 @@
+import { getContract } from "viem";
+import { abi as orderBookAbi } from "../_abi/orderbook";
 import {
   ethersProvider,
   ethersWallet,
@@ -6,8 +8,31 @@ import {
   orderBookContract,
   viemWalletClient,
 } from "../config";
-const permit = await fhenixClient.generatePermit(globalConfig.contractAddress, ethersProvider, ethersWallet);
-const permission = fhenixClient.extractPermitPermission(permit);
+
+type OrderBookContract = typeof orderBookContract;
+type Permission = ReturnType<typeof fhenixClient.extractPermitPermission>;
+
+// Permits are issued per contract, so each market gets its own
+const contracts = new Map<string, { contract: OrderBookContract; permission: Promise<Permission> }>();
+
+function getMarket(contractAddress: `0x${string}`) {
+  const key = contractAddress.toLowerCase();
+  let market = contracts.get(key);
+  if (!market) {
+    const contract =
+      key === globalConfig.contractAddress.toLowerCase()
+        ? orderBookContract
+        : getContract({ abi: orderBookAbi, address: contractAddress, client: viemWalletClient });
+    const permission = fhenixClient
+      .generatePermit(contractAddress, ethersProvider, ethersWallet)
+      .then((permit) => fhenixClient.extractPermitPermission(permit));
+    market = { contract, permission };
+    contracts.set(key, market);
+    // Let a failed permit be generated again on the next request
+    permission.catch(() => contracts.delete(key));
+  }
+  return market;
+}
 
 export type Order = {
   id: number;
@@ -16,17 +41,22 @@ export type Order = {
   price: number;
 };
 
-export async function getOrderById(orderId: bigint): Promise<Order> {
-  const sealedResults = await orderBookContract.read.getOrder([
+export async function getOrderById(
+  orderId: bigint,
+  contractAddress: `0x${string}` = globalConfig.contractAddress,
+): Promise<Order> {
+  const { contract, permission } = getMarket(contractAddress);
+  const { publicKey, signature } = await permission;
+  const sealedResults = await contract.read.getOrder([
     {
-      publicKey: permission.publicKey as `0x${string}`,
-      signature: permission.signature as `0x${string}`,
+      publicKey: publicKey as `0x${string}`,
+      signature: signature as `0x${string}`,
     },
     orderId,
   ]);
-  const side = fhenixClient.unseal(globalConfig.contractAddress, sealedResults[0], viemWalletClient.account.address);
-  const amount = fhenixClient.unseal(globalConfig.contractAddress, sealedResults[1], viemWalletClient.account.address);
-  const price = fhenixClient.unseal(globalConfig.contractAddress, sealedResults[2], viemWalletClient.account.address);
+  const side = fhenixClient.unseal(contractAddress, sealedResults[0], viemWalletClient.account.address);
+  const amount = fhenixClient.unseal(contractAddress, sealedResults[1], viemWalletClient.account.address);
+  const price = fhenixClient.unseal(contractAddress, sealedResults[2], viemWalletClient.account.address);
   return {
     id: Number(orderId),
     side: Boolean(side),